@@ -0,0 +1,61 @@
+use crate::image::{Image, Rgb};
+
+/// Simulates the slow response of the DMG LCD on output frames. It sits after the emulation core
+/// and only changes what is displayed, never the framebuffer.
+pub struct FrameBlender {
+    blend: f32, // weight of the previous frame, 0.5 turns flickering sprites into transparency
+    ghosting: f32, // weight of the previous output, pixels fade out over several frames
+    previous_frame: Vec<[f32; 3]>,
+    previous_output: Vec<[f32; 3]>,
+}
+
+impl FrameBlender {
+    /// Both strengths go from 0, disabled, to below 1.
+    pub fn new(blend: f32, ghosting: f32) -> FrameBlender {
+        FrameBlender {
+            blend,
+            ghosting,
+            previous_frame: Vec::new(),
+            previous_output: Vec::new(),
+        }
+    }
+
+    pub fn apply(&mut self, image: &Image) -> Image {
+        let frame: Vec<[f32; 3]> = image.pixels.iter()
+            .map(|pixel| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])
+            .collect();
+        if self.previous_frame.len() != frame.len() {
+            self.previous_frame = frame.clone();
+            self.previous_output = frame.clone();
+        }
+
+        let output: Vec<[f32; 3]> = frame.iter()
+            .zip(self.previous_frame.iter().zip(self.previous_output.iter()))
+            .map(|(current, (previous_frame, previous_output))| {
+                let blended = FrameBlender::mix(*current, *previous_frame, self.blend);
+                FrameBlender::mix(blended, *previous_output, self.ghosting)
+            })
+            .collect();
+
+        let mut result = Image::new(image.width, image.height);
+        for (pixel, color) in result.pixels.iter_mut().zip(output.iter()) {
+            *pixel = FrameBlender::to_rgb(*color);
+        }
+
+        self.previous_frame = frame;
+        self.previous_output = output;
+        result
+    }
+
+    fn mix(a: [f32; 3], b: [f32; 3], weight: f32) -> [f32; 3] {
+        [
+            a[0] + (b[0] - a[0]) * weight,
+            a[1] + (b[1] - a[1]) * weight,
+            a[2] + (b[2] - a[2]) * weight,
+        ]
+    }
+
+    fn to_rgb(color: [f32; 3]) -> Rgb {
+        [color[0].round() as u8, color[1].round() as u8, color[2].round() as u8]
+    }
+}
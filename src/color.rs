@@ -0,0 +1,59 @@
+use crate::image::Rgb;
+
+const LCD_GAMMA: f32 = 2.5; // a bit steeper than sRGB, the CGB panel has darker midtones
+const OUTPUT_GAMMA: f32 = 2.2;
+const LCD_BRIGHTNESS: f32 = 0.94; // the CGB panel never reaches full white
+
+/// How much of each channel (red, green, blue) of a color ends up in each channel shown by the LCD,
+/// rows add up to 1 so greys stay neutral.
+const LCD_MIX: [[f32; 3]; 3] = [
+    [26.0 / 32.0, 4.0 / 32.0, 2.0 / 32.0],
+    [0.0, 24.0 / 32.0, 8.0 / 32.0],
+    [6.0 / 32.0, 4.0 / 32.0, 22.0 / 32.0],
+];
+
+/// How CGB RGB555 colors are turned into RGB888 for output. The raw values look oversaturated and
+/// too bright on sRGB monitors compared to the CGB LCD.
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum ColorCorrection {
+    None, // each channel widened from 5 to 8 bits
+    GbcLcd, // the LCD gamma, its channels bleeding into each other and its slightly dim white
+    Gamma, // only the LCD gamma, darker midtones without changing the hues
+}
+
+impl ColorCorrection {
+    pub fn from_name(name: &str) -> Option<ColorCorrection> {
+        match name {
+            "none" => Some(ColorCorrection::None),
+            "gbc-lcd" => Some(ColorCorrection::GbcLcd),
+            "gamma" => Some(ColorCorrection::Gamma),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, color: u16) -> Rgb {
+        let channel = |shift: u16| ((color >> shift) & 0x1f) as u8;
+        let (r, g, b) = (channel(0), channel(5), channel(10));
+
+        match self {
+            ColorCorrection::None => [r, g, b].map(|value| value << 3 | value >> 2),
+            ColorCorrection::GbcLcd => LCD_MIX.map(|weights| {
+                let value = weights[0] * r as f32 + weights[1] * g as f32 + weights[2] * b as f32;
+                ColorCorrection::output(ColorCorrection::linear(value), LCD_BRIGHTNESS)
+            }),
+            ColorCorrection::Gamma => {
+                [r, g, b].map(|value| ColorCorrection::output(ColorCorrection::linear(value as f32), 1.0))
+            }
+        }
+    }
+
+    /// Light intensity of a 5 bit channel value on the LCD, from 0 to 1.
+    fn linear(value: f32) -> f32 {
+        (value / 31.0).powf(LCD_GAMMA)
+    }
+
+    /// sRGB encoded channel for a light intensity from 0 to 1, scaled by `brightness` once encoded.
+    fn output(intensity: f32, brightness: f32) -> u8 {
+        (intensity.powf(1.0 / OUTPUT_GAMMA) * brightness * 255.0).round().min(255.0) as u8
+    }
+}
@@ -0,0 +1,117 @@
+use crate::hardware;
+
+const TITLE_FOURTH_LETTER_ADDRESS: usize = 0x137;
+const UNIQUE_CHECKSUMS: usize = 65; // the rest of TITLE_CHECKSUMS also needs the 4th title letter to match
+
+/// Title checksums known to the CGB boot ROM, see `hardware::boot_checksum`. The last entries repeat
+/// checksums shared by several games, which are told apart by the 4th letter of their title in
+/// DUPLICATE_LETTERS. Unknown games and games not licensed by Nintendo get entry 0.
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xd1, 0xdb, 0xf2, 0x3c, 0x8c, 0x92, 0x3d, 0x5c, 0x58, 0xc9, 0x3e, 0x70,
+    0x1d, 0x59, 0x69, 0x19, 0x35, 0xa8, 0x14, 0xaa, 0x75, 0x95, 0x99, 0x34, 0x6f, 0x15, 0xff, 0x97,
+    0x4b, 0x90, 0x17, 0x10, 0x39, 0xf7, 0xf6, 0xa2, 0x49, 0x4e, 0x43, 0x68, 0xe0, 0x8b, 0xf0, 0xce,
+    0x0c, 0x29, 0xe8, 0xb7, 0x86, 0x9a, 0x52, 0x01, 0x9d, 0x71, 0x9c, 0xbd, 0x5d, 0x6d, 0x67, 0x3f,
+    0x6b,
+    0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4,
+    0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4,
+    0xb3,
+];
+
+const DUPLICATE_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// Entry of COMBINATIONS used by each entry of TITLE_CHECKSUMS.
+const CHECKSUM_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50,
+    17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18,
+    29,
+];
+
+/// Offsets into COLORS of the OBJ0, OBJ1 and background palettes. Most start on a palette boundary,
+/// a few start one color early so they borrow the last color of the previous palette.
+const COMBINATIONS: [[usize; 3]; 51] = [
+    [4 * 4, 4 * 4, 29 * 4], [18 * 4, 18 * 4, 18 * 4], [20 * 4, 20 * 4, 20 * 4], [24 * 4, 24 * 4, 24 * 4],
+    [9 * 4, 9 * 4, 9 * 4], [0, 0, 0], [27 * 4, 27 * 4, 27 * 4], [5 * 4, 5 * 4, 5 * 4],
+    [12 * 4, 12 * 4, 12 * 4], [26 * 4, 26 * 4, 26 * 4], [16 * 4, 8 * 4, 8 * 4], [4 * 4, 28 * 4, 28 * 4],
+    [4 * 4, 2 * 4, 2 * 4], [3 * 4, 4 * 4, 4 * 4], [4 * 4, 29 * 4, 29 * 4], [28 * 4, 4 * 4, 28 * 4],
+    [2 * 4, 17 * 4, 2 * 4], [16 * 4, 16 * 4, 8 * 4], [4 * 4, 4 * 4, 7 * 4], [4 * 4, 4 * 4, 18 * 4],
+    [4 * 4, 4 * 4, 20 * 4], [19 * 4, 19 * 4, 9 * 4], [4 * 4 - 1, 4 * 4 - 1, 11 * 4], [17 * 4, 17 * 4, 2 * 4],
+    [4 * 4, 4 * 4, 2 * 4], [4 * 4, 4 * 4, 3 * 4], [28 * 4, 28 * 4, 0], [3 * 4, 3 * 4, 0],
+    [0, 0, 4], [18 * 4, 22 * 4, 18 * 4], [20 * 4, 22 * 4, 20 * 4], [24 * 4, 22 * 4, 24 * 4],
+    [16 * 4, 22 * 4, 8 * 4], [17 * 4, 4 * 4, 13 * 4], [28 * 4 - 1, 0, 14 * 4], [28 * 4 - 1, 4 * 4, 15 * 4],
+    [19 * 4, 22 * 4, 9 * 4], [16 * 4, 28 * 4, 10 * 4], [4 * 4, 23 * 4, 28 * 4], [17 * 4, 22 * 4, 2 * 4],
+    [4 * 4, 0, 2 * 4], [4 * 4, 28 * 4, 3 * 4], [28 * 4, 3 * 4, 0], [3 * 4, 28 * 4, 4 * 4],
+    [21 * 4, 28 * 4, 4 * 4], [3 * 4, 28 * 4, 0], [25 * 4, 3 * 4, 28 * 4], [0, 28 * 4, 8 * 4],
+    [4 * 4, 3 * 4, 28 * 4], [28 * 4, 3 * 4, 6 * 4], [4 * 4, 28 * 4, 29 * 4],
+];
+
+/// The boot ROM palettes, 4 RGB555 colors each.
+const COLORS: [u16; 120] = [
+    0x7fff, 0x32bf, 0x00d0, 0x0000, 0x639f, 0x4279, 0x15b0, 0x04cb,
+    0x7fff, 0x6e31, 0x454a, 0x0000, 0x7fff, 0x1bef, 0x0200, 0x0000,
+    0x7fff, 0x421f, 0x1cf2, 0x0000, 0x7fff, 0x5294, 0x294a, 0x0000,
+    0x7fff, 0x03ff, 0x012f, 0x0000, 0x7fff, 0x03ef, 0x01d6, 0x0000,
+    0x7fff, 0x42b5, 0x3dc8, 0x0000, 0x7e74, 0x03ff, 0x0180, 0x0000,
+    0x67ff, 0x77ac, 0x1a13, 0x2d6b, 0x7ed6, 0x4bff, 0x2175, 0x0000,
+    0x53ff, 0x4a5f, 0x7e52, 0x0000, 0x4fff, 0x7ed2, 0x3a4c, 0x1ce0,
+    0x03ed, 0x7fff, 0x255f, 0x0000, 0x036a, 0x021f, 0x03ff, 0x7fff,
+    0x7fff, 0x01df, 0x0112, 0x0000, 0x231f, 0x035f, 0x00f2, 0x0009,
+    0x7fff, 0x03ea, 0x011f, 0x0000, 0x299f, 0x001a, 0x000c, 0x0000,
+    0x7fff, 0x027f, 0x001f, 0x0000, 0x7fff, 0x03e0, 0x0206, 0x0120,
+    0x7fff, 0x7eeb, 0x001f, 0x7c00, 0x7fff, 0x3fff, 0x7e00, 0x001f,
+    0x7fff, 0x03ff, 0x001f, 0x0000, 0x03ff, 0x001f, 0x000c, 0x0000,
+    0x7fff, 0x033f, 0x0193, 0x0000, 0x0000, 0x4200, 0x037f, 0x7fff,
+    0x7fff, 0x7e8c, 0x7c00, 0x0000, 0x7fff, 0x1bef, 0x6180, 0x0000,
+];
+
+/// Entries of COMBINATIONS picked by holding a direction, alone or with A or B, while the CGB logo shows.
+const BUTTON_COMBINATIONS: [(&str, usize); 12] = [
+    ("right", 1), ("left", 48), ("up", 5), ("down", 8),
+    ("right+a", 0), ("left+a", 40), ("up+a", 43), ("down+a", 3),
+    ("right+b", 6), ("left+b", 7), ("up+b", 28), ("down+b", 49),
+];
+
+/// Colors the CGB boot ROM loads into palette RAM for a DMG game: background palette 0 and object
+/// palettes 0 and 1, which then stand in for BGP, OBP0 and OBP1.
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub struct CompatPalettes {
+    pub background: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+impl CompatPalettes {
+    /// The palettes the boot ROM picks from the title checksum of `cart`.
+    pub fn from_title(cart: &[u8]) -> CompatPalettes {
+        let checksum = hardware::boot_checksum(cart);
+        let letter = cart[TITLE_FOURTH_LETTER_ADDRESS];
+
+        let entry = TITLE_CHECKSUMS.iter().enumerate()
+            .position(|(i, &known)| {
+                known == checksum && (i < UNIQUE_CHECKSUMS || DUPLICATE_LETTERS[i - UNIQUE_CHECKSUMS] == letter)
+            })
+            .unwrap_or(0);
+        CompatPalettes::from_combination(CHECKSUM_COMBINATIONS[entry] as usize)
+    }
+
+    /// The palettes picked with a button combination such as `left` or `up+a`.
+    pub fn from_buttons(name: &str) -> Option<CompatPalettes> {
+        BUTTON_COMBINATIONS.iter()
+            .find(|(buttons, _)| *buttons == name.to_lowercase())
+            .map(|(_, combination)| CompatPalettes::from_combination(*combination))
+    }
+
+    fn from_combination(combination: usize) -> CompatPalettes {
+        let palette = |offset: usize| {
+            let mut colors = [0; 4];
+            colors.copy_from_slice(&COLORS[offset..offset + 4]);
+            colors
+        };
+        let [obj0, obj1, background] = COMBINATIONS[combination];
+        CompatPalettes { background: palette(background), obj0: palette(obj0), obj1: palette(obj1) }
+    }
+}
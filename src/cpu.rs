@@ -1,30 +1,61 @@
+use std::io;
+use std::path::Path;
+
 use bitmatch::bitmatch;
 
+use crate::filter::Filter;
+use crate::hardware::Hardware;
+use crate::image::ImageFormat;
 use crate::memory::Memory;
+use crate::palette::Palette;
 use crate::register::{Register, Flags};
 use crate::interrupt::{Interrupt, Interrupts};
 use crate::utils::{join_8_to_16, join_8_to_16_lsf};
 
 pub type Cycles = usize;
 
+const SPEED_SWITCH_DOTS: Cycles = 2050 * 4; // on the normal speed clock, whichever way the speed changes
+
 pub struct CPU {
     register: Register,
     pub memory: Memory,
     interrupt: Interrupt,
     halted: bool,
+    stopped: bool, // low power mode after STOP, left when a selected joypad line goes low
 }
 
 impl CPU {
-    pub fn new(filepath: &str) -> CPU {
+    /// Starts `filepath` in the post-boot state of `hardware`, detected from the header when `None`.
+    pub fn new(filepath: &str, hardware: Option<Hardware>) -> CPU {
+        let memory = Memory::new(filepath, hardware);
         CPU {
-            register: Register::new(),
-            memory: Memory::new(filepath),
+            register: Register::new(memory.hardware(), memory.boot_checksum()),
+            memory,
             interrupt: Interrupt::new(),
             halted: false,
+            stopped: false,
         }
     }
 
+    /// Saves the current frame with its shades mapped through `palette` and upscaled by `filter`.
+    pub fn screenshot(&self, path: &Path, format: ImageFormat, palette: &Palette, filter: &Filter) -> io::Result<()> {
+        filter.apply(&self.memory.gpu.frame_image(palette)).save(path, format)
+    }
+
     pub fn step(&mut self) -> Cycles {
+        match self.memory.take_dma_stall() { // the CPU waits for the VRAM DMA copies
+            0 => {},
+            n => return n,
+        }
+
+        if self.stopped {
+            if self.memory.joypad.read() & 0x0f == 0x0f {
+                self.memory.stop_timer(4);
+                return 4
+            }
+            self.stopped = false;
+        }
+
         match self.interrupt_step() {
             0 => {},
             n => return n,
@@ -329,7 +360,7 @@ impl CPU {
                 self.register.set_carry_flag(true);
                 4
             }
-            "0001_0000" => { panic!("Unimplemented stop"); 4 } // stop todo what to do?
+            "0001_0000" => self.stop(),
             "1111_0011" => { self.interrupt.delayed_disable = 2; 4 } // di
             "1111_1011" => { self.interrupt.delayed_enable = 2; 4 } // ei
             // rotations and shifts (some in exec_alt)
@@ -408,6 +439,22 @@ impl CPU {
         }
     }
 
+    /// STOP resets DIV and switches the CPU speed when KEY1 asked for it on CGB, which takes 8200 dots.
+    /// Otherwise it enters the low power mode.
+    fn stop(&mut self) -> Cycles {
+        self.read_immediate_8(); // stop is followed by a padding byte
+        self.memory.write_8(0xff04, 0);
+        let cycles = if self.memory.switch_speed() {
+            // returned in cycles of the new speed, which `Memory::step` converts back to dots
+            if self.memory.double_speed() { SPEED_SWITCH_DOTS * 2 } else { SPEED_SWITCH_DOTS }
+        } else {
+            self.stopped = true;
+            4
+        };
+        self.memory.stop_timer(cycles); // DIV stays 0 until the CPU runs again
+        cycles
+    }
+
     fn alu(&mut self, y: u8, n: u8) {
         let a = self.register.a;
         let carry_flag = self.register.get_carry_flag() as u8;
@@ -0,0 +1,152 @@
+use std::ops::Range;
+
+use crate::cpu::Cycles;
+use crate::hardware::Hardware;
+
+const OAM_DMA_LENGTH: usize = 0xa0;
+const OAM_DMA_BYTE_CYCLES: Cycles = 4; // one byte per M-cycle
+
+/// OAM DMA started by writing the source page to FF46.
+pub struct OamDma {
+    source: u8, // FF46
+    index: usize,
+    cycles: Cycles,
+    active: bool,
+}
+
+impl OamDma {
+    /// FF46 reads 0xff after the DMG boot ROM and 0x00 after the CGB one.
+    pub fn new(hardware: Hardware) -> OamDma {
+        OamDma {
+            source: if hardware == Hardware::Dmg { 0xff } else { 0x00 },
+            index: 0,
+            cycles: 0,
+            active: false,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.source
+    }
+
+    pub fn write(&mut self, n: u8) {
+        self.source = n;
+        self.index = 0;
+        self.cycles = 0;
+        self.active = true;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Source address of the byte copied into OAM at `index`. Pages above 0xdf read from the echo of work ram.
+    pub fn source_address(&self, index: usize) -> usize {
+        let page = if self.source >= 0xe0 { self.source - 0x20 } else { self.source };
+        (page as usize) << 8 | index
+    }
+
+    /// Advances the transfer and returns the OAM indices that have to be copied in this step.
+    pub fn step(&mut self, cycles: Cycles) -> Range<usize> {
+        if !self.active {
+            return 0..0
+        }
+
+        self.cycles += cycles;
+        let start = self.index;
+        let end = (start + self.cycles / OAM_DMA_BYTE_CYCLES).min(OAM_DMA_LENGTH);
+        self.cycles %= OAM_DMA_BYTE_CYCLES;
+
+        self.index = end;
+        if self.index == OAM_DMA_LENGTH {
+            self.active = false;
+        }
+        start..end
+    }
+}
+
+pub const HDMA_BLOCK_LENGTH: usize = 0x10;
+const HDMA_BLOCK_CYCLES: Cycles = 32; // 8 M-cycles at normal speed, 16 at double speed
+
+#[derive(Copy,Clone,PartialEq)]
+enum HdmaMode {
+    GeneralPurpose,
+    HBlank,
+}
+
+/// CGB VRAM DMA, set up through FF51-FF54 and started by writing the length to FF55. A general purpose
+/// transfer copies all its 16 byte blocks at once, an HBlank transfer one block at the start of each
+/// HBlank. The CPU is stalled while blocks are copied.
+pub struct Hdma {
+    source: u16, // FF51 and FF52
+    destination: u16, // FF53 and FF54, an offset into VRAM
+    blocks: u8, // blocks left
+    mode: Option<HdmaMode>, // transfer in progress
+}
+
+impl Hdma {
+    pub fn new() -> Hdma {
+        Hdma {
+            source: 0,
+            destination: 0,
+            blocks: 0,
+            mode: None,
+        }
+    }
+
+    /// Only FF55 can be read: the blocks left minus one, with bit 7 clear while an HBlank transfer is
+    /// active. It reads 0xff once a transfer completes, and keeps the blocks left when one is cancelled.
+    pub fn read(&self, i: usize) -> u8 {
+        match i {
+            0xff55 => {
+                let blocks = self.blocks.wrapping_sub(1) & 0x7f;
+                if self.mode == Some(HdmaMode::HBlank) { blocks } else { 0x80 | blocks }
+            }
+            _ => 0xff,
+        }
+    }
+
+    /// Writing FF55 starts a transfer, or cancels the HBlank transfer in progress when bit 7 is clear.
+    pub fn write(&mut self, i: usize, n: u8) {
+        match i {
+            0xff51 => self.source = (self.source & 0x00ff) | (n as u16) << 8,
+            0xff52 => self.source = (self.source & 0xff00) | (n & 0xf0) as u16,
+            0xff53 => self.destination = (self.destination & 0x00ff) | ((n & 0x1f) as u16) << 8,
+            0xff54 => self.destination = (self.destination & 0xff00) | (n & 0xf0) as u16,
+            0xff55 => {
+                if self.mode == Some(HdmaMode::HBlank) && n & 0x80 == 0 {
+                    self.mode = None;
+                    return
+                }
+                self.blocks = (n & 0x7f) + 1;
+                self.mode = Some(if n & 0x80 != 0 { HdmaMode::HBlank } else { HdmaMode::GeneralPurpose });
+            }
+            _ => panic!("Invalid hdma write {}", i)
+        }
+    }
+
+    /// Source address and VRAM offset of the next block, if one has to be copied now. HBlank transfers
+    /// only copy at the start of an HBlank.
+    pub fn next_block(&mut self, hblank: bool) -> Option<(usize, usize)> {
+        match self.mode {
+            Some(HdmaMode::GeneralPurpose) => {}
+            Some(HdmaMode::HBlank) if hblank => {}
+            _ => return None,
+        }
+
+        let block = (self.source as usize, self.destination as usize);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_LENGTH as u16);
+        self.destination = (self.destination + HDMA_BLOCK_LENGTH as u16) & 0x1ff0;
+        self.blocks -= 1;
+        if self.blocks == 0 || self.destination == 0 {
+            self.blocks = 0;
+            self.mode = None;
+        }
+        Some(block)
+    }
+
+    /// CPU cycles stalled for each block, the copy takes the same time at both speeds.
+    pub fn block_cycles(double_speed: bool) -> Cycles {
+        if double_speed { HDMA_BLOCK_CYCLES * 2 } else { HDMA_BLOCK_CYCLES }
+    }
+}
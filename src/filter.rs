@@ -0,0 +1,238 @@
+use crate::image::{Image, Rgb};
+
+/// How much the LCD grid and scanline effects darken their lines.
+const EFFECT_DARKEN: f32 = 0.7;
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Scaler {
+    Nearest(usize),
+    Scale2x,
+    Scale3x,
+    Xbr2x,
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum LcdEffect {
+    None,
+    Grid, // darkens the gap between pixels in both directions
+    Scanlines, // darkens the bottom row of each pixel
+}
+
+/// CPU side upscaling for exported frames, an upscaler followed by an optional LCD effect.
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub struct Filter {
+    pub scaler: Scaler,
+    pub effect: LcdEffect,
+}
+
+impl Filter {
+    pub const NONE: Filter = Filter { scaler: Scaler::Nearest(1), effect: LcdEffect::None };
+
+    pub fn apply(&self, image: &Image) -> Image {
+        let mut output = match self.scaler {
+            Scaler::Nearest(1) => Image { width: image.width, height: image.height, pixels: image.pixels.clone() },
+            Scaler::Nearest(factor) => Filter::nearest(image, factor),
+            Scaler::Scale2x => Filter::scale2x(image),
+            Scaler::Scale3x => Filter::scale3x(image),
+            Scaler::Xbr2x => Filter::xbr2x(image),
+        };
+        Filter::lcd_effect(&mut output, self.scaler.factor(), self.effect);
+        output
+    }
+
+    fn nearest(image: &Image, factor: usize) -> Image {
+        let mut output = Image::new(image.width * factor, image.height * factor);
+        for y in 0..output.height {
+            for x in 0..output.width {
+                output.pixels[y * output.width + x] = image.pixels[(y / factor) * image.width + x / factor];
+            }
+        }
+        output
+    }
+
+    /// Pixel at `x + dx`, `y + dy`, clamped to the image edges.
+    fn neighbour(image: &Image, x: usize, y: usize, dx: isize, dy: isize) -> Rgb {
+        let x = (x as isize + dx).clamp(0, image.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, image.height as isize - 1) as usize;
+        image.pixels[y * image.width + x]
+    }
+
+    /// Writes the `factor` x `factor` block of output pixels for source pixel `x`, `y`.
+    fn put_block(output: &mut Image, x: usize, y: usize, factor: usize, block: &[Rgb]) {
+        for (i, pixel) in block.iter().enumerate() {
+            output.pixels[(y * factor + i / factor) * output.width + x * factor + i % factor] = *pixel;
+        }
+    }
+
+    /// Scale2x, also known as EPX.
+    fn scale2x(image: &Image) -> Image {
+        let mut output = Image::new(image.width * 2, image.height * 2);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let n = |dx, dy| Filter::neighbour(image, x, y, dx, dy);
+                let (b, d, e, f, h) = (n(0, -1), n(-1, 0), n(0, 0), n(1, 0), n(0, 1));
+
+                let block = if b != h && d != f {
+                    [
+                        if d == b { d } else { e },
+                        if b == f { f } else { e },
+                        if d == h { d } else { e },
+                        if h == f { f } else { e },
+                    ]
+                } else {
+                    [e; 4]
+                };
+                Filter::put_block(&mut output, x, y, 2, &block);
+            }
+        }
+        output
+    }
+
+    /// Scale3x, the 3x extension of EPX.
+    fn scale3x(image: &Image) -> Image {
+        let mut output = Image::new(image.width * 3, image.height * 3);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let n = |dx, dy| Filter::neighbour(image, x, y, dx, dy);
+                let (a, b, c) = (n(-1, -1), n(0, -1), n(1, -1));
+                let (d, e, f) = (n(-1, 0), n(0, 0), n(1, 0));
+                let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+
+                let block = if b != h && d != f {
+                    [
+                        if d == b { d } else { e },
+                        if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                        if b == f { f } else { e },
+                        if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                        e,
+                        if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                        if d == h { d } else { e },
+                        if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                        if h == f { f } else { e },
+                    ]
+                } else {
+                    [e; 9]
+                };
+                Filter::put_block(&mut output, x, y, 3, &block);
+            }
+        }
+        output
+    }
+
+    /// First level of xBR at 2x. Each corner looks at a 5x5 neighbourhood to decide whether an edge
+    /// crosses it, and if so blends the corner halfway towards the closest neighbour along the edge.
+    fn xbr2x(image: &Image) -> Image {
+        let mut output = Image::new(image.width * 2, image.height * 2);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let e = image.pixels[y * image.width + x];
+                let mut block = [e; 4];
+                for (corner, (sx, sy)) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter().enumerate() {
+                    // neighbours mirrored so that the corner being computed is always the bottom right one
+                    let n = |dx: isize, dy: isize| Filter::neighbour(image, x, y, dx * sx, dy * sy);
+                    block[corner] = Filter::xbr_corner(&n);
+                }
+                Filter::put_block(&mut output, x, y, 2, &block);
+            }
+        }
+        output
+    }
+
+    fn xbr_corner<N: Fn(isize, isize) -> Rgb>(n: &N) -> Rgb {
+        let (b, c, d, e, f) = (n(0, -1), n(1, -1), n(-1, 0), n(0, 0), n(1, 0));
+        let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+        let (f4, i4) = (n(2, 0), n(2, 1));
+        let (h5, i5) = (n(0, 2), n(1, 2));
+
+        if e == f || e == h {
+            return e
+        }
+
+        let dist = Filter::distance;
+        let edge = dist(e, c) + dist(e, g) + dist(i, f4) + dist(i, h5) + 4.0 * dist(h, f);
+        let across = dist(h, d) + dist(h, i5) + dist(f, i4) + dist(f, b) + 4.0 * dist(e, i);
+        if edge >= across {
+            return e
+        }
+
+        let towards = if dist(e, f) <= dist(e, h) { f } else { h };
+        Filter::mix(e, towards)
+    }
+
+    /// Perceptual distance between two colors, weighting luma over chroma like xBR does.
+    fn distance(a: Rgb, b: Rgb) -> f32 {
+        let yuv = |c: Rgb| {
+            let (r, g, b) = (c[0] as f32, c[1] as f32, c[2] as f32);
+            let y = 0.299 * r + 0.587 * g + 0.114 * b;
+            (y, 0.492 * (b - y), 0.877 * (r - y))
+        };
+        let (ya, ua, va) = yuv(a);
+        let (yb, ub, vb) = yuv(b);
+        48.0 * (ya - yb).abs() + 7.0 * (ua - ub).abs() + 6.0 * (va - vb).abs()
+    }
+
+    fn mix(a: Rgb, b: Rgb) -> Rgb {
+        [
+            ((a[0] as u16 + b[0] as u16) / 2) as u8,
+            ((a[1] as u16 + b[1] as u16) / 2) as u8,
+            ((a[2] as u16 + b[2] as u16) / 2) as u8,
+        ]
+    }
+
+    fn lcd_effect(image: &mut Image, factor: usize, effect: LcdEffect) {
+        if factor < 2 || effect == LcdEffect::None {
+            return
+        }
+
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let last_row = y % factor == factor - 1;
+                let last_column = x % factor == factor - 1;
+                let darken = match effect {
+                    LcdEffect::Grid => last_row || last_column,
+                    LcdEffect::Scanlines => last_row,
+                    LcdEffect::None => false,
+                };
+                if darken {
+                    let pixel = &mut image.pixels[y * image.width + x];
+                    for channel in pixel.iter_mut() {
+                        *channel = (*channel as f32 * EFFECT_DARKEN) as u8;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Scaler {
+    pub fn from_name(name: &str) -> Option<Scaler> {
+        match name {
+            "scale2x" | "epx" => Some(Scaler::Scale2x),
+            "scale3x" => Some(Scaler::Scale3x),
+            "xbr" | "xbr2x" => Some(Scaler::Xbr2x),
+            _ => match name.trim_end_matches('x').parse() {
+                Ok(factor) if (1..=8).contains(&factor) => Some(Scaler::Nearest(factor)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        match self {
+            Scaler::Nearest(factor) => *factor,
+            Scaler::Scale2x | Scaler::Xbr2x => 2,
+            Scaler::Scale3x => 3,
+        }
+    }
+}
+
+impl LcdEffect {
+    pub fn from_name(name: &str) -> Option<LcdEffect> {
+        match name {
+            "none" => Some(LcdEffect::None),
+            "grid" => Some(LcdEffect::Grid),
+            "scanlines" => Some(LcdEffect::Scanlines),
+            _ => None,
+        }
+    }
+}
@@ -1,7 +1,67 @@
+mod fifo;
+mod palette_ram;
+
+use crate::color::ColorCorrection;
+use crate::compat::CompatPalettes;
 use crate::cpu::Cycles;
+use crate::hardware::Hardware;
+use crate::image::{Image, Rgb};
+use crate::palette::Palette;
+use crate::interrupt::Interrupts;
+use fifo::PixelFifo;
+use palette_ram::PaletteRam;
 
-#[derive(Copy,Clone)]
-enum PixelGrayScale {
+const OAM_SCAN_CYCLES: Cycles = 80;
+const PIXEL_TRANSFER_CYCLES: Cycles = 172;
+const LINE_CYCLES: Cycles = 456;
+const SCREEN_LINES: u8 = 144;
+const TOTAL_LINES: u8 = 154;
+const LAST_LINE_LY_CYCLES: Cycles = 4; // LY already reads 0 after these dots of line 153
+const FRAME_CYCLES: Cycles = LINE_CYCLES * TOTAL_LINES as Cycles;
+const FIRST_LINE_SKIPPED_CYCLES: Cycles = 4; // the first line after turning the LCD on is this much shorter
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+const SPRITES_PER_LINE: usize = 10;
+const VRAM_BANK_SIZE: usize = 0x2000;
+const TILES_PER_BANK: usize = 128 * 3;
+const CGB_PALETTE_MASK: u8 = 0x07; // palette number in the map attributes and in the sprite flags
+
+/// How mode 3 is emulated. `Scanline` draws each line at once after a fixed 172 dots, `Fifo` runs
+/// the pixel FIFOs dot by dot so mode 3 length and HBlank start vary like on hardware.
+#[derive(Copy,Clone,PartialEq)]
+pub enum Renderer {
+    Scanline,
+    Fifo,
+}
+
+#[derive(Copy,Clone,PartialEq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    PixelTransfer = 3,
+}
+
+pub enum StatInterrupt {
+    HBlank = 1 << 3,
+    VBlank = 1 << 4,
+    Oam = 1 << 5,
+    Coincidence = 1 << 6,
+}
+
+pub enum Lcdc {
+    BgEnable = 1 << 0,
+    ObjEnable = 1 << 1,
+    ObjSize = 1 << 2,
+    BgTileMap = 1 << 3,
+    TileData = 1 << 4,
+    WindowEnable = 1 << 5,
+    WindowTileMap = 1 << 6,
+    LcdEnable = 1 << 7,
+}
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum PixelGrayScale {
     Zero,
     One,
     Two,
@@ -17,40 +77,197 @@ impl PixelGrayScale {
             (false, false) => PixelGrayScale::Zero,
         }
     }
+
+    fn from_index(i: u8) -> PixelGrayScale {
+        PixelGrayScale::from_bools(i & 0b01 != 0, i & 0b10 != 0)
+    }
+
+    /// Maps a color index through a palette register such as BGP.
+    fn from_palette(palette: u8, color: PixelGrayScale) -> PixelGrayScale {
+        PixelGrayScale::from_index(palette >> (color as u8 * 2))
+    }
+
+    pub fn to_rgb(self, palette: &Palette) -> Rgb {
+        palette.colors[self as usize]
+    }
+}
+
+/// A framebuffer pixel. DMG frames keep the shade so the output palette is picked when converting,
+/// CGB frames hold the RGB555 color from the palette RAM.
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Pixel {
+    Shade(PixelGrayScale),
+    Rgb555(u16),
+}
+
+impl Pixel {
+    const WHITE: Pixel = Pixel::Rgb555(0x7fff);
+
+    /// RGB888 color, shades go through `palette` and RGB555 colors through `correction`.
+    pub fn to_rgb(self, palette: &Palette, correction: ColorCorrection) -> Rgb {
+        match self {
+            Pixel::Shade(shade) => shade.to_rgb(palette),
+            Pixel::Rgb555(color) => correction.apply(color),
+        }
+    }
 }
 
+pub enum SpriteFlags {
+    Bank = 1 << 3, // CGB only
+    Palette = 1 << 4,
+    XFlip = 1 << 5,
+    YFlip = 1 << 6,
+    BehindBackground = 1 << 7,
+}
+
+/// An OAM entry. Y and X keep the hardware offsets of 16 and 8.
+#[derive(Copy,Clone)]
+pub struct Sprite {
+    pub index: u8, // position in OAM, sprites with a lower index win overlaps on CGB
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+}
+
+impl Sprite {
+    pub fn from_oam(oam: &[u8], index: usize) -> Sprite {
+        let entry = &oam[index * 4..index * 4 + 4];
+        Sprite { index: index as u8, y: entry[0], x: entry[1], tile: entry[2], flags: entry[3] }
+    }
+
+    pub fn flag(&self, flag: SpriteFlags) -> bool {
+        self.flags & (flag as u8) != 0
+    }
+
+    /// Object palette number in the flags, CGB only.
+    pub fn cgb_palette(&self) -> u8 {
+        self.flags & CGB_PALETTE_MASK
+    }
+}
+
+/// Attributes of a background map entry, stored at the same address in VRAM bank 1. CGB only.
+enum TileAttributes {
+    Bank = 1 << 3,
+    XFlip = 1 << 5,
+    YFlip = 1 << 6,
+    Priority = 1 << 7, // the background covers sprites unless its color is 0
+}
+
+/// A background or window pixel: its color index and the attributes of its map entry.
+type BackgroundPixel = (PixelGrayScale, u8);
+
 type Tile = Vec<Vec<PixelGrayScale>>;
 fn tile_new() -> Vec<Vec<PixelGrayScale>> {
     vec![vec![PixelGrayScale::Zero; 8]; 8]
 }
 
 pub struct GPU {
-    vram: Vec<u8>,
-    tile_cache: Vec<Tile>,
+    hardware: Hardware,
+    vram: Vec<u8>, // both banks, bank 1 only exists on CGB
+    vram_bank: usize, // FF4F
+    tile_cache: [Vec<Tile>; 2], // one per VRAM bank
+    framebuffer: Vec<Pixel>,
+    line_sprites: Vec<Sprite>, // chosen during OAM scan, in fetch order
+    renderer: Renderer,
+    color_correction: ColorCorrection, // for the RGB555 colors in output images
+    fifo: PixelFifo,
+
+    mode: Mode,
+    line_cycles: Cycles, // dots elapsed in the current line
+    lcdc: u8, // FF40
+    stat: u8, // FF41, only the interrupt select bits are stored
+    scy: u8, // FF42
+    scx: u8, // FF43
+    ly: u8, // FF44
+    lyc: u8, // FF45
+    bgp: u8, // FF47
+    obp0: u8, // FF48
+    obp1: u8, // FF49
+    wy: u8, // FF4A
+    wx: u8, // FF4B
+    bg_palettes: PaletteRam, // FF68 and FF69, CGB only
+    obj_palettes: PaletteRam, // FF6A and FF6B, CGB only
+    window_line: u8, // internal counter, only advances on lines where the window was drawn
+    window_y_reached: bool, // LY matched WY at some point of the current frame
+    frame: usize, // frames completed since power on
+    first_frame: bool, // the frame after turning the LCD on, the panel doesn't show it
+    off_cycles: Cycles, // dots elapsed since the blank frame shown while the LCD is off
+    stat_line: bool, // state of the STAT interrupt line, requests happen on its rising edge
+    hblanks: usize, // times mode 3 ended since the last `take_hblanks`
+    interrupt_flag: u8, // requested interrupts not yet passed to memory
 }
 
 impl GPU {
-    pub fn new() -> GPU {
+    pub fn new(hardware: Hardware) -> GPU {
         GPU {
-            vram: vec![0; 0x2000],
-            tile_cache: vec![tile_new(); 128 * 3],
+            hardware,
+            vram: vec![0; VRAM_BANK_SIZE * 2],
+            vram_bank: 0,
+            tile_cache: [vec![tile_new(); TILES_PER_BANK], vec![tile_new(); TILES_PER_BANK]],
+            framebuffer: vec![Pixel::Shade(PixelGrayScale::Zero); SCREEN_WIDTH * SCREEN_HEIGHT],
+            line_sprites: Vec::with_capacity(SPRITES_PER_LINE),
+            renderer: Renderer::Scanline,
+            color_correction: ColorCorrection::None,
+            fifo: PixelFifo::new(),
+
+            mode: Mode::OamScan,
+            line_cycles: 0,
+            lcdc: 0x91,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xfc,
+            obp0: 0xff,
+            obp1: 0xff,
+            wy: 0,
+            wx: 0,
+            bg_palettes: PaletteRam::new(),
+            obj_palettes: PaletteRam::new(),
+            window_line: 0,
+            window_y_reached: false,
+            frame: 0,
+            first_frame: false,
+            off_cycles: 0,
+            stat_line: false,
+            hblanks: 0,
+            interrupt_flag: 0,
         }
     }
 
+    /// VRAM is locked for the CPU while the PPU reads it in mode 3, unless the LCD is off.
+    pub fn vram_accessible(&self) -> bool {
+        !self.lcdc_bit(Lcdc::LcdEnable) || self.mode != Mode::PixelTransfer
+    }
+
+    /// OAM is locked for the CPU during OAM scan and pixel transfer, unless the LCD is off.
+    pub fn oam_accessible(&self) -> bool {
+        !self.lcdc_bit(Lcdc::LcdEnable) || self.mode == Mode::HBlank || self.mode == Mode::VBlank
+    }
+
+    /// The CGB palette RAM is locked like VRAM, while the PPU reads it in mode 3.
+    fn palette_accessible(&self) -> bool {
+        self.vram_accessible()
+    }
+
+    /// Reads the VRAM bank selected by VBK.
     pub fn read_vram(&self, address: usize) -> u8 {
-        self.vram[address]
+        self.vram[self.vram_bank * VRAM_BANK_SIZE + address]
     }
 
+    /// Writes the VRAM bank selected by VBK.
     pub fn write_vram(&mut self, address: usize, value: u8) {
-        self.vram[address] = value;
+        self.vram[self.vram_bank * VRAM_BANK_SIZE + address] = value;
 
         if address < 0x1800 {
-            self.update_tile_cache(address);
+            self.update_tile_cache(self.vram_bank, address);
         }
     }
 
-    fn update_tile_cache(&mut self, address: usize) {
-        let first_byte_address = address & 0xfffe;
+    fn update_tile_cache(&mut self, bank: usize, address: usize) {
+        let first_byte_address = bank * VRAM_BANK_SIZE + (address & 0xfffe);
         let byte1 = self.vram[first_byte_address];
         let byte2 = self.vram[first_byte_address + 1];
 
@@ -58,12 +275,610 @@ impl GPU {
         let row_index = (address % 16) / 2;
         for pixel_index in 0..8 {
             let mask = 1 << (7 - pixel_index);
-            self.tile_cache[tile_index][row_index][pixel_index] =
+            self.tile_cache[bank][tile_index][row_index][pixel_index] =
                 PixelGrayScale::from_bools(byte1 & mask != 0, byte2 & mask != 0);
         }
     }
 
-    pub fn step(&self, cycles: Cycles) {
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+    }
+
+    /// Loads the colors DMG games are shown with in DMG-compat mode, like the CGB boot ROM does.
+    pub fn set_compat_palettes(&mut self, palettes: &CompatPalettes) {
+        self.bg_palettes.set_palette(0, palettes.background);
+        self.obj_palettes.set_palette(0, palettes.obj0);
+        self.obj_palettes.set_palette(1, palettes.obj1);
+    }
+
+    pub fn read_register(&self, i: usize) -> u8 {
+        match i {
+            0xff40 => self.lcdc,
+            0xff41 => self.read_stat(),
+            0xff42 => self.scy,
+            0xff43 => self.scx,
+            0xff44 => self.ly,
+            0xff45 => self.lyc,
+            0xff47 => self.bgp,
+            0xff48 => self.obp0,
+            0xff49 => self.obp1,
+            0xff4a => self.wy,
+            0xff4b => self.wx,
+            0xff4f => if self.hardware.is_cgb() { 0xfe | self.vram_bank as u8 } else { 0xff },
+            0xff68..=0xff6b if !self.hardware.is_cgb() => 0xff,
+            0xff68 => self.bg_palettes.read_index(),
+            0xff69 => if self.palette_accessible() { self.bg_palettes.read_data() } else { 0xff },
+            0xff6a => self.obj_palettes.read_index(),
+            0xff6b => if self.palette_accessible() { self.obj_palettes.read_data() } else { 0xff },
+            _ => panic!("Invalid gpu register read {}", i)
+        }
+    }
+
+    pub fn write_register(&mut self, i: usize, n: u8) {
+        match i {
+            0xff40 => {
+                let was_enabled = self.lcdc_bit(Lcdc::LcdEnable);
+                self.lcdc = n;
+                match (was_enabled, self.lcdc_bit(Lcdc::LcdEnable)) {
+                    (true, false) => self.lcd_off(),
+                    (false, true) => self.lcd_on(),
+                    _ => {}
+                }
+            }
+            0xff41 => { // mode and coincidence bits are read only
+                self.stat = n & 0x78;
+                self.update_stat_line();
+            }
+            0xff42 => self.scy = n,
+            0xff43 => self.scx = n,
+            0xff44 => { // writing LY restarts the frame, with the LCD off the PPU stays in mode 0
+                self.line_cycles = 0;
+                if self.lcdc_bit(Lcdc::LcdEnable) {
+                    self.mode = Mode::OamScan;
+                }
+                self.set_ly(0);
+            }
+            0xff45 => {
+                self.lyc = n;
+                self.update_stat_line();
+            }
+            0xff47 => self.bgp = n,
+            0xff48 => self.obp0 = n,
+            0xff49 => self.obp1 = n,
+            0xff4a => self.wy = n,
+            0xff4b => self.wx = n,
+            0xff4f => if self.hardware.is_cgb() { self.vram_bank = (n & 0x01) as usize },
+            0xff68..=0xff6b if !self.hardware.is_cgb() => {}
+            0xff68 => self.bg_palettes.write_index(n),
+            0xff69 => self.bg_palettes.write_data(n, !self.palette_accessible()),
+            0xff6a => self.obj_palettes.write_index(n),
+            0xff6b => self.obj_palettes.write_data(n, !self.palette_accessible()),
+            _ => panic!("Invalid gpu register write {}", i)
+        }
+    }
+
+    /// STAT with the unused bit 7 set, the interrupt selects, the LY=LYC flag and the current mode.
+    fn read_stat(&self) -> u8 {
+        let coincidence = ((self.ly == self.lyc) as u8) << 2;
+        0x80 | self.stat | coincidence | self.mode as u8
+    }
+
+    /// Turning the LCD off stops the PPU on line 0 in mode 0 and blanks the panel.
+    fn lcd_off(&mut self) {
+        self.line_cycles = 0;
+        self.off_cycles = 0;
+        self.ly = 0;
+        self.mode = Mode::HBlank;
+        self.stat_line = false;
+        self.blank_frame();
+    }
+
+    /// Turning the LCD on starts line 0 without an OAM scan, STAT reads mode 0 until pixel transfer,
+    /// and the line is a few dots short. The panel only shows the frame after this one, so this frame
+    /// neither requests the VBlank interrupt nor advances the frame counter.
+    fn lcd_on(&mut self) {
+        self.line_cycles = FIRST_LINE_SKIPPED_CYCLES;
+        self.window_line = 0;
+        self.window_y_reached = false;
+        self.first_frame = true;
+        self.set_mode(Mode::HBlank);
+    }
+
+    pub fn step(&mut self, cycles: Cycles, oam: &[u8]) {
+        if !self.lcdc_bit(Lcdc::LcdEnable) {
+            self.step_off(cycles);
+            return
+        }
+        for _ in 0..cycles {
+            self.tick(oam);
+        }
+    }
+
+    /// With the LCD off the PPU is stopped, but a blank frame still counts every frame period so
+    /// the frontends keep drawing and reading input.
+    fn step_off(&mut self, cycles: Cycles) {
+        self.off_cycles += cycles;
+        if self.off_cycles >= FRAME_CYCLES {
+            self.off_cycles -= FRAME_CYCLES;
+            self.frame += 1;
+        }
+    }
+
+    /// Advances the PPU a single dot, switching modes at the line and frame boundaries.
+    fn tick(&mut self, oam: &[u8]) {
+        self.line_cycles += 1;
+
+        match self.mode {
+            Mode::OamScan => {
+                if self.line_cycles == 1 && self.ly == self.wy {
+                    self.window_y_reached = true;
+                }
+                if self.line_cycles == OAM_SCAN_CYCLES {
+                    self.start_pixel_transfer(oam);
+                }
+            }
+            Mode::PixelTransfer => match self.renderer {
+                Renderer::Scanline => {
+                    if self.line_cycles == OAM_SCAN_CYCLES + PIXEL_TRANSFER_CYCLES {
+                        self.render_scanline();
+                        self.start_hblank();
+                    }
+                }
+                Renderer::Fifo => {
+                    if self.fifo_tick() {
+                        self.start_hblank();
+                    }
+                }
+            },
+            Mode::HBlank => {
+                if self.first_frame && self.ly == 0 && self.line_cycles == OAM_SCAN_CYCLES {
+                    // the first line after turning the LCD on has no mode 2
+                    self.window_y_reached = self.ly == self.wy;
+                    self.start_pixel_transfer(oam);
+                } else if self.line_cycles == LINE_CYCLES {
+                    self.line_cycles = 0;
+                    self.set_ly(self.ly + 1);
+
+                    if self.ly == SCREEN_LINES {
+                        if self.first_frame {
+                            self.first_frame = false;
+                            self.blank_frame();
+                        } else {
+                            self.frame += 1;
+                            self.interrupt_flag |= Interrupts::VBlank as u8;
+                        }
+                        self.set_mode(Mode::VBlank);
+                    } else {
+                        self.set_mode(Mode::OamScan);
+                    }
+                }
+            }
+            Mode::VBlank => {
+                if self.ly == TOTAL_LINES - 1 && self.line_cycles == LAST_LINE_LY_CYCLES {
+                    self.set_ly(0);
+                }
+
+                if self.line_cycles == LINE_CYCLES {
+                    self.line_cycles = 0;
+
+                    if self.ly == 0 { // line 153 was the last one
+                        self.window_line = 0;
+                        self.window_y_reached = false;
+                        self.set_mode(Mode::OamScan);
+                    } else {
+                        self.set_ly(self.ly + 1);
+                    }
+                }
+            }
+        }
+    }
+
+    fn start_pixel_transfer(&mut self, oam: &[u8]) {
+        self.scan_oam(oam);
+        if self.renderer == Renderer::Fifo {
+            self.fifo_start_line();
+        }
+        self.set_mode(Mode::PixelTransfer);
+    }
+
+    fn start_hblank(&mut self) {
+        self.hblanks += 1;
+        self.set_mode(Mode::HBlank);
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.update_stat_line();
+    }
+
+    fn set_ly(&mut self, ly: u8) {
+        self.ly = ly;
+        self.update_stat_line();
+    }
+
+    fn stat_select(&self, source: StatInterrupt) -> bool {
+        self.stat & (source as u8) != 0
+    }
+
+    /// Recomputes the single STAT interrupt line. Its sources are OR'd together and only a rising edge
+    /// requests the interrupt, so a source going active while another one holds the line high is lost
+    /// (STAT blocking). Entering VBlank also counts as the mode 2 source, like on DMG. The line stays
+    /// low while the LCD is off.
+    fn update_stat_line(&mut self) {
+        if !self.lcdc_bit(Lcdc::LcdEnable) {
+            self.stat_line = false;
+            return
+        }
+        let mode_source = match self.mode {
+            Mode::HBlank => self.stat_select(StatInterrupt::HBlank),
+            Mode::VBlank => {
+                self.stat_select(StatInterrupt::VBlank)
+                    || (self.ly == SCREEN_LINES && self.line_cycles == 0 && self.stat_select(StatInterrupt::Oam))
+            }
+            Mode::OamScan => self.stat_select(StatInterrupt::Oam),
+            Mode::PixelTransfer => false,
+        };
+        let line = mode_source || (self.ly == self.lyc && self.stat_select(StatInterrupt::Coincidence));
+
+        if line && !self.stat_line {
+            self.interrupt_flag |= Interrupts::LCD as u8;
+        }
+        self.stat_line = line;
+    }
+
+    /// Pixels of the last rendered frame, row by row, `SCREEN_WIDTH` pixels per row.
+    pub fn framebuffer(&self) -> &[Pixel] {
+        &self.framebuffer
+    }
+
+    /// Number of frames completed, it increases when VBlank starts.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// The framebuffer converted to RGB888, DMG shades mapped through `palette` and CGB colors through
+    /// the color correction. It holds a whole frame during VBlank.
+    pub fn frame_image(&self, palette: &Palette) -> Image {
+        let mut image = Image::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        for (rgb, pixel) in image.pixels.iter_mut().zip(self.framebuffer().iter()) {
+            *rgb = pixel.to_rgb(palette, self.color_correction);
+        }
+        image
+    }
+
+    /// All tiles in VRAM, 16 per row in tile number order, with bank 1 to the right of bank 0 when
+    /// `both_banks` is set. With `use_bgp` the color indices go through the background palette like on
+    /// screen, BGP and `palette` or CGB palette 0, otherwise they are drawn with the fixed greyscale palette.
+    pub fn tile_set_image(&self, use_bgp: bool, both_banks: bool, palette: &Palette) -> Image {
+        let columns = 16;
+        let rows = TILES_PER_BANK / columns;
+        let banks = if both_banks { 2 } else { 1 };
+        let mut image = Image::new(banks * columns * 8, rows * 8);
+
+        let tiles = self.tile_cache[..banks].iter().enumerate()
+            .flat_map(|(bank, tiles)| tiles.iter().enumerate().map(move |(index, tile)| (bank, index, tile)));
+        for (bank, index, tile) in tiles {
+            let (left, top) = ((bank * columns + index % columns) * 8, (index / columns) * 8);
+            for (y, row) in tile.iter().enumerate() {
+                for (x, color) in row.iter().enumerate() {
+                    let rgb = if use_bgp {
+                        self.background_color(*color, 0).to_rgb(palette, self.color_correction)
+                    } else {
+                        color.to_rgb(&Palette::GRAYSCALE)
+                    };
+                    image.pixels[(top + y) * image.width + left + x] = rgb;
+                }
+            }
+        }
+        image
+    }
+
+    /// The 32x32 tile numbers of the map at `map_address`, 0x9800 or 0x9c00.
+    pub fn tile_map(&self, map_address: usize) -> &[u8] {
+        let map = map_address - 0x8000;
+        &self.vram[map..map + 0x400]
+    }
+
+    /// The 32x32 attribute bytes of the map at `map_address`, from VRAM bank 1. Only on CGB.
+    pub fn tile_map_attributes(&self, map_address: usize) -> Option<&[u8]> {
+        let map = VRAM_BANK_SIZE + map_address - 0x8000;
+        if self.hardware.is_cgb() { Some(&self.vram[map..map + 0x400]) } else { None }
+    }
+
+    /// The whole 256x256 map at `map_address` with the current tile data addressing and background
+    /// palettes, and the SCX/SCY viewport outlined in `viewport` color.
+    pub fn tile_map_image(&self, map_address: usize, palette: &Palette, viewport: Rgb) -> Image {
+        let map = map_address - 0x8000;
+        let mut image = Image::new(256, 256);
+
+        for y in 0..256 {
+            for x in 0..256 {
+                let (color, attributes) = self.tile_map_pixel(map, x as u8, y as u8);
+                image.pixels[y * 256 + x] = self.background_color(color, attributes).to_rgb(palette, self.color_correction);
+            }
+        }
+
+        let (width, height) = (SCREEN_WIDTH as u8, SCREEN_HEIGHT as u8);
+        for i in 0..width {
+            let x = self.scx.wrapping_add(i) as usize;
+            image.pixels[self.scy as usize * 256 + x] = viewport;
+            image.pixels[self.scy.wrapping_add(height - 1) as usize * 256 + x] = viewport;
+        }
+        for i in 0..height {
+            let y = self.scy.wrapping_add(i) as usize;
+            image.pixels[y * 256 + self.scx as usize] = viewport;
+            image.pixels[y * 256 + self.scx.wrapping_add(width - 1) as usize] = viewport;
+        }
+        image
+    }
+
+    /// A sprite as it is drawn with the current object size and its object palette. Transparent pixels
+    /// are blank, the lightest color of `palette` or white on CGB.
+    pub fn sprite_image(&self, sprite: &Sprite, palette: &Palette) -> Image {
+        let height = self.sprite_height() as usize;
+        let mut image = Image::new(8, height);
+
+        for y in 0..height {
+            for (x, color) in self.sprite_row_at(sprite, y).iter().enumerate() {
+                let pixel = match color {
+                    PixelGrayScale::Zero => self.blank_pixel(),
+                    color => self.sprite_color(*color, sprite),
+                };
+                image.pixels[y * 8 + x] = pixel.to_rgb(palette, self.color_correction);
+            }
+        }
+        image
+    }
+
+    /// All 40 OAM entries drawn with `sprite_image`, 8 per row in OAM order, one pixel apart.
+    pub fn oam_image(&self, oam: &[u8], palette: &Palette, background: Rgb) -> Image {
+        let (columns, rows) = (8, 5);
+        let height = self.sprite_height() as usize;
+        let mut image = Image::new(columns * 9 + 1, rows * (height + 1) + 1);
+        image.pixels.iter_mut().for_each(|pixel| *pixel = background);
+
+        for index in 0..40 {
+            let sprite = self.sprite_image(&Sprite::from_oam(oam, index), palette);
+            let (left, top) = ((index % columns) * 9 + 1, (index / columns) * (height + 1) + 1);
+            for y in 0..sprite.height {
+                for x in 0..sprite.width {
+                    image.pixels[(top + y) * image.width + left + x] = sprite.pixels[y * sprite.width + x];
+                }
+            }
+        }
+        image
+    }
+
+    fn lcdc_bit(&self, bit: Lcdc) -> bool {
+        self.lcdc & (bit as u8) != 0
+    }
+
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc_bit(Lcdc::ObjSize) { 16 } else { 8 }
+    }
+
+    /// Picks the first 10 sprites in OAM order that overlap the current line, then orders them the
+    /// way they are fetched: smaller X first and, on equal X, lower OAM index first. That is also the
+    /// DMG priority, CGB gives priority to the lower OAM index alone.
+    fn scan_oam(&mut self, oam: &[u8]) {
+        let height = self.sprite_height();
+        let line = self.ly.wrapping_add(16);
+
+        self.line_sprites.clear();
+        for index in 0..40 {
+            let sprite = Sprite::from_oam(oam, index);
+            if line >= sprite.y && line < sprite.y.wrapping_add(height) && sprite.y < 160 {
+                self.line_sprites.push(sprite);
+                if self.line_sprites.len() == SPRITES_PER_LINE {
+                    break
+                }
+            }
+        }
+        self.line_sprites.sort_by_key(|sprite| sprite.x); // stable, keeps OAM order on ties
+    }
+
+    fn render_scanline(&mut self) {
+        let line = self.ly as usize * SCREEN_WIDTH;
+        let window = self.window_visible();
+
+        for x in 0..SCREEN_WIDTH {
+            let color = if !self.background_enabled() {
+                (PixelGrayScale::Zero, 0)
+            } else if window && x + 7 >= self.wx as usize {
+                self.window_pixel(x as u8)
+            } else {
+                self.background_pixel(x as u8)
+            };
+            let sprite = if self.lcdc_bit(Lcdc::ObjEnable) { self.sprite_pixel(x as u8) } else { None };
+            let pixel = self.mix_pixel(color, sprite);
+            self.framebuffer[line + x] = pixel;
+        }
+
+        if window {
+            self.window_line += 1;
+        }
+    }
+
+    /// Final color of a pixel from its background pixel and the sprite pixel on top of it, if any.
+    /// On CGB the map attributes can also put the background in front, and clearing LCDC bit 0 takes
+    /// the priority away from the background altogether.
+    fn mix_pixel(&self, (color, attributes): BackgroundPixel, sprite: Option<(PixelGrayScale, Sprite)>) -> Pixel {
+        if let Some((sprite_color, sprite)) = sprite {
+            let visible = sprite_color != PixelGrayScale::Zero && self.lcdc_bit(Lcdc::ObjEnable);
+            let behind = sprite.flag(SpriteFlags::BehindBackground) || attributes & TileAttributes::Priority as u8 != 0;
+            let background_priority = !self.hardware.is_cgb() || self.lcdc_bit(Lcdc::BgEnable);
+            if visible && (!behind || !background_priority || color == PixelGrayScale::Zero) {
+                return self.sprite_color(sprite_color, &sprite)
+            }
+        }
+        self.background_color(color, attributes)
+    }
+
+    /// A background color index through BGP, or on CGB through the palette in its map attributes. In
+    /// DMG-compat mode the BGP shade picks a color of background palette 0.
+    fn background_color(&self, color: PixelGrayScale, attributes: u8) -> Pixel {
+        match self.hardware {
+            Hardware::Cgb => Pixel::Rgb555(self.bg_palettes.color(attributes & CGB_PALETTE_MASK, color as u8)),
+            Hardware::DmgCompat => {
+                Pixel::Rgb555(self.bg_palettes.color(0, PixelGrayScale::from_palette(self.bgp, color) as u8))
+            }
+            Hardware::Dmg => Pixel::Shade(PixelGrayScale::from_palette(self.bgp, color)),
+        }
+    }
+
+    /// A sprite color index through OBP0/OBP1, or on CGB through the palette in its flags. In DMG-compat
+    /// mode the OBP shade picks a color of object palette 0 or 1.
+    fn sprite_color(&self, color: PixelGrayScale, sprite: &Sprite) -> Pixel {
+        let obp = if sprite.flag(SpriteFlags::Palette) { self.obp1 } else { self.obp0 };
+        match self.hardware {
+            Hardware::Cgb => Pixel::Rgb555(self.obj_palettes.color(sprite.cgb_palette(), color as u8)),
+            Hardware::DmgCompat => {
+                let palette = sprite.flag(SpriteFlags::Palette) as u8;
+                Pixel::Rgb555(self.obj_palettes.color(palette, PixelGrayScale::from_palette(obp, color) as u8))
+            }
+            Hardware::Dmg => Pixel::Shade(PixelGrayScale::from_palette(obp, color)),
+        }
+    }
+
+    /// What the panel shows with the LCD off.
+    fn blank_pixel(&self) -> Pixel {
+        if self.hardware == Hardware::Dmg { Pixel::Shade(PixelGrayScale::Zero) } else { Pixel::WHITE }
+    }
+
+    fn blank_frame(&mut self) {
+        let blank = self.blank_pixel();
+        self.framebuffer.fill(blank);
+    }
+
+    /// Whether the background and window are drawn. LCDC bit 0 only blanks them on DMG.
+    fn background_enabled(&self) -> bool {
+        self.hardware.is_cgb() || self.lcdc_bit(Lcdc::BgEnable)
+    }
+
+    /// Whether the window covers part of the current line. On DMG it also needs the background enabled.
+    fn window_visible(&self) -> bool {
+        self.lcdc_bit(Lcdc::WindowEnable)
+            && self.background_enabled()
+            && self.window_y_reached
+            && self.wx <= 166
+    }
+
+    /// Color index of the window at screen column `x`. The window starts at WX - 7, so when WX < 7
+    /// its first columns fall off the left edge of the screen instead of shifting it right.
+    fn window_pixel(&self, x: u8) -> BackgroundPixel {
+        self.tile_map_pixel(self.window_map(), x + 7 - self.wx, self.window_line)
+    }
+
+    fn window_map(&self) -> usize {
+        if self.lcdc_bit(Lcdc::WindowTileMap) { 0x1c00 } else { 0x1800 }
+    }
+
+    /// Color index of the background at screen column `x` on the current line, scrolled by SCX/SCY.
+    fn background_pixel(&self, x: u8) -> BackgroundPixel {
+        self.tile_map_pixel(self.background_map(), x.wrapping_add(self.scx), self.ly.wrapping_add(self.scy))
+    }
+
+    fn background_map(&self) -> usize {
+        if self.lcdc_bit(Lcdc::BgTileMap) { 0x1c00 } else { 0x1800 }
+    }
+
+    /// Color index and owner of the highest priority opaque sprite pixel at screen column `x`.
+    fn sprite_pixel(&self, x: u8) -> Option<(PixelGrayScale, Sprite)> {
+        let x = x.wrapping_add(8);
+
+        let mut pixel: Option<(PixelGrayScale, Sprite)> = None;
+        for sprite in &self.line_sprites {
+            if x < sprite.x || x >= sprite.x.wrapping_add(8) || sprite.x >= 168 {
+                continue
+            }
+
+            let color = self.sprite_row(sprite)[(x - sprite.x) as usize];
+            if color != PixelGrayScale::Zero {
+                if !self.hardware.is_cgb() {
+                    return Some((color, *sprite))
+                }
+                if pixel.is_none_or(|(_, owner)| sprite.index < owner.index) {
+                    pixel = Some((color, *sprite));
+                }
+            }
+        }
+        pixel
+    }
+
+    /// Whether `sprite` covers an opaque pixel of `owner` where they overlap, by OAM index on CGB.
+    /// On DMG the sprite fetched first keeps the pixel.
+    fn sprite_has_priority(&self, sprite: &Sprite, owner: &Sprite) -> bool {
+        self.hardware.is_cgb() && sprite.index < owner.index
+    }
+
+    /// The 8 color indices of `sprite` on the current line, left to right, with flips applied.
+    fn sprite_row(&self, sprite: &Sprite) -> [PixelGrayScale; 8] {
+        let row = self.ly.wrapping_add(16).wrapping_sub(sprite.y) as usize;
+        self.sprite_row_at(sprite, row & (self.sprite_height() as usize - 1))
+    }
+
+    /// The 8 color indices of row `row` of `sprite`, counted from its top edge, with flips applied.
+    fn sprite_row_at(&self, sprite: &Sprite, mut row: usize) -> [PixelGrayScale; 8] {
+        let height = self.sprite_height() as usize;
+        if sprite.flag(SpriteFlags::YFlip) {
+            row = height - 1 - row;
+        }
+        let tile = if height == 16 { sprite.tile & 0xfe } else { sprite.tile } as usize + row / 8;
+        let bank = (self.hardware.is_cgb() && sprite.flag(SpriteFlags::Bank)) as usize;
+
+        let mut pixels = [PixelGrayScale::Zero; 8];
+        for (column, pixel) in pixels.iter_mut().enumerate() {
+            let column = if sprite.flag(SpriteFlags::XFlip) { 7 - column } else { column };
+            *pixel = self.tile_cache[bank][tile][row % 8][column];
+        }
+        pixels
+    }
+
+    fn tile_map_pixel(&self, map: usize, x: u8, y: u8) -> BackgroundPixel {
+        let (row, attributes) = self.tile_map_row(map, x / 8, y);
+        (row[x as usize % 8], attributes)
+    }
+
+    /// Row `y % 8` of the tile at column `tile_x` and line `y` of a 32x32 tile map, and the attributes
+    /// of its map entry. On CGB the attributes choose the tile bank and flip the tile.
+    fn tile_map_row(&self, map: usize, tile_x: u8, y: u8) -> ([PixelGrayScale; 8], u8) {
+        let entry = map + (y as usize / 8) * 32 + (tile_x as usize & 31);
+        let attributes = if self.hardware.is_cgb() { self.vram[VRAM_BANK_SIZE + entry] } else { 0 };
+        let attribute = |attribute: TileAttributes| attributes & attribute as u8 != 0;
+
+        let row = if attribute(TileAttributes::YFlip) { 7 - y as usize % 8 } else { y as usize % 8 };
+        let tile = &self.tile_cache[attribute(TileAttributes::Bank) as usize][self.tile_index(self.vram[entry])];
+        let mut pixels = [PixelGrayScale::Zero; 8];
+        pixels.copy_from_slice(&tile[row]);
+        if attribute(TileAttributes::XFlip) {
+            pixels.reverse();
+        }
+        (pixels, attributes)
+    }
+
+    /// Tile cache index for a tile number, 0x8000 unsigned or 0x8800 signed addressing.
+    fn tile_index(&self, tile_number: u8) -> usize {
+        if self.lcdc_bit(Lcdc::TileData) {
+            tile_number as usize
+        } else {
+            (256 + tile_number as i8 as isize) as usize
+        }
+    }
+
+    /// How many HBlanks started on visible lines since the last call, HBlank DMA copies a block in each.
+    /// A long step such as the speed switch can span several lines.
+    pub fn take_hblanks(&mut self) -> usize {
+        let hblanks = self.hblanks;
+        self.hblanks = 0;
+        hblanks
+    }
 
+    pub fn update_interrupt_flag(&mut self, flags: &mut u8) {
+        *flags |= self.interrupt_flag;
+        self.interrupt_flag = 0;
     }
 }
@@ -0,0 +1,234 @@
+use std::collections::VecDeque;
+
+use crate::cpu::Cycles;
+use super::{BackgroundPixel, GPU, Lcdc, PixelGrayScale, Sprite};
+
+const FETCH_STEP_CYCLES: Cycles = 2;
+const SPRITE_FETCH_CYCLES: Cycles = 6;
+
+#[derive(Copy,Clone,PartialEq)]
+enum FetcherStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+/// Background/window tile fetcher. Every step but the push takes two dots, the push is retried
+/// each dot until the background FIFO is empty.
+struct Fetcher {
+    step: FetcherStep,
+    cycles: Cycles,
+    tile_x: u8, // tile column of the next fetch, relative to SCX for the background
+    row: [PixelGrayScale; 8],
+    attributes: u8, // of the map entry the row was fetched from
+    first_fetch: bool, // the first fetch of a line is thrown away
+}
+
+impl Fetcher {
+    fn new() -> Fetcher {
+        Fetcher {
+            step: FetcherStep::Tile,
+            cycles: 0,
+            tile_x: 0,
+            row: [PixelGrayScale::Zero; 8],
+            attributes: 0,
+            first_fetch: true,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.step = FetcherStep::Tile;
+        self.cycles = 0;
+        self.tile_x = 0;
+    }
+}
+
+/// Mode 3 state of the FIFO renderer. Mode 3 lasts as long as it takes to shift out 160 pixels,
+/// so its length grows with the SCX fine scroll, window restarts and sprite fetches.
+pub struct PixelFifo {
+    background: VecDeque<BackgroundPixel>,
+    sprites: VecDeque<(PixelGrayScale, Sprite)>,
+    fetcher: Fetcher,
+    lcd_x: u8, // next screen column to be output
+    discard: u8, // pixels still to drop, SCX % 8 at the start of the line
+    window: bool, // fetcher is reading the window tile map
+    next_sprite: usize, // first sprite of the line not fetched yet
+    sprite_fetch: Option<Cycles>, // dots left of the sprite fetch in progress
+    sprite_tile: Option<u8>, // tile column of the last sprite fetch
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            background: VecDeque::with_capacity(16),
+            sprites: VecDeque::with_capacity(8),
+            fetcher: Fetcher::new(),
+            lcd_x: 0,
+            discard: 0,
+            window: false,
+            next_sprite: 0,
+            sprite_fetch: None,
+            sprite_tile: None,
+        }
+    }
+}
+
+impl GPU {
+    pub(super) fn fifo_start_line(&mut self) {
+        self.fifo = PixelFifo::new();
+        self.fifo.discard = self.scx % 8;
+    }
+
+    /// Runs one dot of mode 3. Returns true once the whole line has been output.
+    pub(super) fn fifo_tick(&mut self) -> bool {
+        if let Some(cycles) = self.fifo.sprite_fetch {
+            if cycles > 1 {
+                self.fifo.sprite_fetch = Some(cycles - 1);
+            } else {
+                self.fifo.sprite_fetch = None;
+                self.fetch_sprite();
+            }
+            return false
+        }
+
+        if self.sprite_pending() {
+            // the fetch waits for the background fetcher to finish its tile, so only the first sprite
+            // on a tile pays for that, then 6 dots for the sprite itself
+            let column = if self.fifo.window {
+                (self.fifo.lcd_x + 7).saturating_sub(self.wx)
+            } else {
+                self.fifo.lcd_x.wrapping_add(self.scx)
+            };
+            let wait = if self.fifo.sprite_tile == Some(column / 8) { 0 } else { 5 - (column % 8).min(5) };
+            self.fifo.sprite_tile = Some(column / 8);
+            self.fifo.sprite_fetch = Some(SPRITE_FETCH_CYCLES + wait as Cycles - 1);
+            return false
+        }
+
+        // the window starts when the shifter reaches its column with a pixel to output, including the
+        // pixels about to be pushed, so at column 0 the restart comes after the discarded first fetch
+        // and still costs a whole tile fetch
+        let shifting = !self.fifo.background.is_empty() || self.fifo.fetcher.step == FetcherStep::Push;
+        if !self.fifo.window && shifting && self.window_visible() && self.fifo.lcd_x + 7 >= self.wx {
+            self.fifo.window = true;
+            self.fifo.background.clear();
+            self.fifo.fetcher.restart();
+            self.fifo.discard = 7u8.saturating_sub(self.wx);
+        }
+
+        self.fetcher_tick();
+        self.shift_pixel();
+
+        if self.fifo.lcd_x as usize == super::SCREEN_WIDTH {
+            if self.fifo.window {
+                self.window_line += 1;
+            }
+            return true
+        }
+        false
+    }
+
+    /// Whether the next sprite of the line starts at the column about to be output.
+    fn sprite_pending(&self) -> bool {
+        if !self.lcdc_bit(Lcdc::ObjEnable) {
+            return false
+        }
+        match self.line_sprites.get(self.fifo.next_sprite) {
+            Some(sprite) => sprite.x <= self.fifo.lcd_x + 8 && sprite.x < 168,
+            None => false,
+        }
+    }
+
+    /// Merges the pending sprite into the sprite FIFO. Pixels already owned by an opaque sprite pixel
+    /// are kept, which gives the DMG priority as sprites arrive by X and then OAM order. On CGB an
+    /// opaque pixel of a sprite with a lower OAM index replaces them.
+    fn fetch_sprite(&mut self) {
+        let sprite = self.line_sprites[self.fifo.next_sprite];
+        self.fifo.next_sprite += 1;
+
+        let row = self.sprite_row(&sprite);
+        let skip = (self.fifo.lcd_x + 8 - sprite.x) as usize; // columns already off the left edge
+        for (i, color) in row.iter().enumerate().skip(skip) {
+            let slot = i - skip;
+            if slot < self.fifo.sprites.len() {
+                let (owner_color, owner) = self.fifo.sprites[slot];
+                let replace = owner_color == PixelGrayScale::Zero
+                    || (*color != PixelGrayScale::Zero && self.sprite_has_priority(&sprite, &owner));
+                if replace {
+                    self.fifo.sprites[slot] = (*color, sprite);
+                }
+            } else {
+                self.fifo.sprites.push_back((*color, sprite));
+            }
+        }
+    }
+
+    fn fetcher_tick(&mut self) {
+        let fetcher = &mut self.fifo.fetcher;
+
+        if fetcher.step != FetcherStep::Push {
+            fetcher.cycles += 1;
+            if fetcher.cycles < FETCH_STEP_CYCLES {
+                return
+            }
+            fetcher.cycles = 0;
+        }
+
+        match fetcher.step {
+            FetcherStep::Tile => fetcher.step = FetcherStep::DataLow,
+            FetcherStep::DataLow => fetcher.step = FetcherStep::DataHigh,
+            FetcherStep::DataHigh => {
+                // the tile cache already holds the decoded row, so both data reads collapse into this one
+                let (row, attributes) = self.fetch_row();
+                let fetcher = &mut self.fifo.fetcher;
+                fetcher.row = row;
+                fetcher.attributes = attributes;
+                if fetcher.first_fetch {
+                    fetcher.first_fetch = false;
+                    fetcher.step = FetcherStep::Tile;
+                } else {
+                    fetcher.step = FetcherStep::Push;
+                }
+            }
+            FetcherStep::Push => {
+                if self.fifo.background.is_empty() {
+                    let attributes = fetcher.attributes;
+                    self.fifo.background.extend(fetcher.row.iter().map(|color| (*color, attributes)));
+                    fetcher.tile_x = fetcher.tile_x.wrapping_add(1);
+                    fetcher.step = FetcherStep::Tile;
+                }
+            }
+        }
+    }
+
+    fn fetch_row(&self) -> ([PixelGrayScale; 8], u8) {
+        if !self.background_enabled() {
+            return ([PixelGrayScale::Zero; 8], 0)
+        }
+
+        let tile_x = self.fifo.fetcher.tile_x;
+        if self.fifo.window {
+            self.tile_map_row(self.window_map(), tile_x, self.window_line)
+        } else {
+            self.tile_map_row(self.background_map(), tile_x.wrapping_add(self.scx / 8), self.ly.wrapping_add(self.scy))
+        }
+    }
+
+    fn shift_pixel(&mut self) {
+        let color = match self.fifo.background.pop_front() {
+            Some(color) => color,
+            None => return,
+        };
+
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return
+        }
+
+        let sprite = self.fifo.sprites.pop_front();
+        let pixel = self.mix_pixel(color, sprite);
+        self.framebuffer[self.ly as usize * super::SCREEN_WIDTH + self.fifo.lcd_x as usize] = pixel;
+        self.fifo.lcd_x += 1;
+    }
+}
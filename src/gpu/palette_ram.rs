@@ -0,0 +1,61 @@
+use crate::utils::{join_8_to_16_lsf, split_16_to_8_lsf};
+
+const PALETTE_RAM_SIZE: usize = 64; // 8 palettes of 4 colors, 2 bytes each
+
+/// CGB color palette memory, accessed through an index register (BCPS/OCPS) and a data register
+/// (BCPD/OCPD). Colors are little endian RGB555, red in the low bits.
+pub struct PaletteRam {
+    data: [u8; PALETTE_RAM_SIZE],
+    index: u8,
+    auto_increment: bool, // advance the index after each data write
+}
+
+impl PaletteRam {
+    pub fn new() -> PaletteRam {
+        PaletteRam {
+            data: [0xff; PALETTE_RAM_SIZE], // the boot ROM leaves every color white
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    /// BCPS/OCPS with the unused bit 6 set.
+    pub fn read_index(&self) -> u8 {
+        (self.auto_increment as u8) << 7 | 0x40 | self.index
+    }
+
+    pub fn write_index(&mut self, n: u8) {
+        self.index = n & 0x3f;
+        self.auto_increment = n & 0x80 != 0;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    /// Writes at the index unless `blocked`, in mode 3. The index still advances either way.
+    pub fn write_data(&mut self, n: u8, blocked: bool) {
+        if !blocked {
+            self.data[self.index as usize] = n;
+        }
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3f;
+        }
+    }
+
+    /// Replaces the 4 colors of palette `palette`.
+    pub fn set_palette(&mut self, palette: u8, colors: [u16; 4]) {
+        for (color, rgb555) in colors.iter().enumerate() {
+            let i = (palette as usize & 7) * 8 + color * 2;
+            let (low, high) = split_16_to_8_lsf(*rgb555);
+            self.data[i] = low;
+            self.data[i + 1] = high;
+        }
+    }
+
+    /// Color `color` of palette `palette`, as RGB555.
+    pub fn color(&self, palette: u8, color: u8) -> u16 {
+        let i = (palette as usize & 7) * 8 + (color as usize & 3) * 2;
+        join_8_to_16_lsf(self.data[i], self.data[i + 1]) & 0x7fff
+    }
+}
@@ -0,0 +1,52 @@
+const CGB_FLAG_ADDRESS: usize = 0x143;
+const TITLE_ADDRESS: usize = 0x134;
+const OLD_LICENSEE_ADDRESS: usize = 0x14b;
+const NEW_LICENSEE_ADDRESS: usize = 0x144;
+
+/// The console being emulated. `DmgCompat` is a Game Boy Color running a cartridge without color
+/// support, its boot ROM locks the CGB features off but leaves different register values than a DMG.
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Hardware {
+    Dmg,
+    Cgb,
+    DmgCompat,
+}
+
+impl Hardware {
+    pub fn from_name(name: &str) -> Option<Hardware> {
+        match name {
+            "dmg" => Some(Hardware::Dmg),
+            "cgb" => Some(Hardware::Cgb),
+            "dmg-compat" => Some(Hardware::DmgCompat),
+            _ => None,
+        }
+    }
+
+    /// CGB mode when the header flag at 0x143 says the game supports it (0x80) or requires it (0xc0),
+    /// DMG otherwise.
+    pub fn detect(cart: &[u8]) -> Hardware {
+        match cart.get(CGB_FLAG_ADDRESS) {
+            Some(flag) if flag & 0x80 != 0 => Hardware::Cgb,
+            _ => Hardware::Dmg,
+        }
+    }
+
+    /// Whether the CGB features (banks, color palettes, double speed) are enabled.
+    pub fn is_cgb(self) -> bool {
+        self == Hardware::Cgb
+    }
+}
+
+/// The value the CGB boot ROM leaves in B: the sum of the 16 title bytes for games licensed by
+/// Nintendo, which it uses to pick a palette, 0 for the rest.
+pub fn boot_checksum(cart: &[u8]) -> u8 {
+    let nintendo = match cart[OLD_LICENSEE_ADDRESS] {
+        0x01 => true,
+        0x33 => &cart[NEW_LICENSEE_ADDRESS..NEW_LICENSEE_ADDRESS + 2] == b"01",
+        _ => false,
+    };
+    if !nintendo {
+        return 0
+    }
+    cart[TITLE_ADDRESS..TITLE_ADDRESS + 16].iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
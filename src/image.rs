@@ -0,0 +1,73 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub type Rgb = [u8; 3];
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name.to_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            _ => None,
+        }
+    }
+
+    /// Format matching the file extension, PNG unless it is `.ppm`.
+    pub fn from_path(path: &Path) -> ImageFormat {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ImageFormat::from_name)
+            .unwrap_or(ImageFormat::Png)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+}
+
+/// An RGB888 picture, row by row.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgb>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image { width, height, pixels: vec![[0, 0, 0]; width * height] }
+    }
+
+    pub fn save(&self, path: &Path, format: ImageFormat) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            ImageFormat::Png => self.write_png(&mut writer)?,
+            ImageFormat::Ppm => self.write_ppm(&mut writer)?,
+        }
+        writer.flush()
+    }
+
+    /// Binary (P6) PPM.
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.pixels.concat())
+    }
+
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut png_writer = encoder.write_header().map_err(io::Error::other)?;
+        png_writer.write_image_data(&self.pixels.concat()).map_err(io::Error::other)
+    }
+}
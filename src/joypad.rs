@@ -0,0 +1,74 @@
+use crate::interrupt::Interrupts;
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum Button {
+    Right = 1 << 0,
+    Left = 1 << 1,
+    Up = 1 << 2,
+    Down = 1 << 3,
+    A = 1 << 4,
+    B = 1 << 5,
+    Select = 1 << 6,
+    Start = 1 << 7,
+}
+
+pub const BUTTONS: [Button; 8] = [
+    Button::Right, Button::Left, Button::Up, Button::Down,
+    Button::A, Button::B, Button::Select, Button::Start,
+];
+
+/// The P1 register (FF00). The directions are the low nibble of `pressed` and the buttons the high one.
+pub struct Joypad {
+    select: u8, // bits 4 and 5 of P1, a line is selected when its bit is 0
+    pressed: u8,
+    interrupt: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0x30,
+            pressed: 0,
+            interrupt: false,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & 0x10 == 0 {
+            lines |= self.pressed & 0x0f;
+        }
+        if self.select & 0x20 == 0 {
+            lines |= self.pressed >> 4;
+        }
+        0xc0 | self.select | (!lines & 0x0f)
+    }
+
+    pub fn write(&mut self, n: u8) {
+        self.select = n & 0x30;
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        self.pressed & (button as u8) != 0
+    }
+
+    /// Presses or releases `button`. A press requests the keypad interrupt.
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        if pressed && !self.is_pressed(button) {
+            self.interrupt = true;
+        }
+
+        if pressed {
+            self.pressed |= button as u8;
+        } else {
+            self.pressed &= !(button as u8);
+        }
+    }
+
+    pub fn update_interrupt_flag(&mut self, flags: &mut u8) {
+        if self.interrupt {
+            *flags |= Interrupts::Keypad as u8;
+            self.interrupt = false;
+        }
+    }
+}
@@ -1,26 +1,138 @@
+mod blend;
+mod color;
+mod compat;
 mod memory;
 mod register;
 mod cpu;
+mod dma;
+mod filter;
 mod interrupt;
+mod joypad;
 mod gpu;
+mod hardware;
+mod image;
+mod options;
+mod palette;
+mod terminal;
 mod timer;
 mod utils;
+mod viewer;
 
+use std::env;
+use std::path::Path;
+use std::process;
+
+use blend::FrameBlender;
 use cpu::CPU;
+use image::{Image, ImageFormat};
+use options::{Options, USAGE};
+use terminal::Terminal;
 
 fn main() {
+    let options = Options::parse(env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{}\n\n{}", error, USAGE);
+        process::exit(1);
+    });
+
     let clock_frequency: usize = 4194304; // Hertz
     let frame_rate: f64 = 59.63;
     let cycles_per_frame: usize = (clock_frequency as f64 / frame_rate).round() as usize;
 
-    let mut cpu = CPU::new("roms/Tetris (World) (Rev A).gb");
-    let mut cycles: usize = 0; // TODO usize or u32?
-    loop {
-        cycles = 0;
+    let mut cpu = CPU::new(&options.rom, options.hardware);
+    cpu.memory.gpu.set_renderer(options.renderer);
+    cpu.memory.gpu.set_color_correction(options.color_correction);
+    if let Some(palettes) = &options.compat_palettes {
+        cpu.memory.gpu.set_compat_palettes(palettes);
+    }
+
+    let mut blender = if options.blend > 0.0 || options.ghosting > 0.0 {
+        Some(FrameBlender::new(options.blend, options.ghosting))
+    } else {
+        None
+    };
+    let mut terminal = if options.terminal {
+        match Terminal::new(options.terminal_colors, frame_rate) {
+            Ok(terminal) => Some(terminal),
+            Err(error) => {
+                eprintln!("Failed to set up the terminal: {}", error);
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    'emulation: loop {
+        let mut cycles: usize = 0; // TODO usize or u32?
         while cycles < cycles_per_frame {
-            cycles += cpu.step();
+            let frame = cpu.memory.gpu.frame();
+            let step_cycles = cpu.step();
+            cycles += cpu.memory.step(step_cycles); // the frame is timed on the normal speed clock
+
+            if cpu.memory.gpu.frame() != frame {
+                // the blender sees every frame, whatever ends up displayed or saved
+                let blended = blender.as_mut()
+                    .map(|blender| blender.apply(&cpu.memory.gpu.frame_image(&options.palette)));
+                on_frame(&cpu, blended.as_ref(), &options);
+                if let Some(terminal) = &mut terminal {
+                    let image = blended.unwrap_or_else(|| cpu.memory.gpu.frame_image(&options.palette));
+                    if let Err(error) = terminal.draw(&image) {
+                        eprintln!("Failed to draw: {}", error);
+                    }
+                    if !terminal.update_joypad(&mut cpu.memory.joypad) {
+                        break 'emulation
+                    }
+                    terminal.wait_frame();
+                }
+                if options.frames == Some(cpu.memory.gpu.frame()) {
+                    break 'emulation
+                }
+            }
+        }
+    }
+
+    drop(terminal);
+    on_exit(&cpu, &options);
+}
+
+/// Saves the frame if it is due, `blended` is the frame after the blending and ghosting filter when it is enabled.
+fn on_frame(cpu: &CPU, blended: Option<&Image>, options: &Options) {
+    let frame = cpu.memory.gpu.frame();
+
+    if let Some(every) = options.screenshot_every {
+        if frame.is_multiple_of(every) {
+            let name = format!("frame_{:06}.{}", frame, options.screenshot_format.extension());
+            let path = options.screenshot_dir.join(name);
+            let result = match blended {
+                Some(image) => options.filter.apply(image).save(&path, options.screenshot_format),
+                None => cpu.screenshot(&path, options.screenshot_format, &options.palette, &options.filter),
+            };
+            if let Err(error) = result {
+                eprintln!("Failed to save {}: {}", path.display(), error);
+            }
         }
+    }
+}
+
+fn on_exit(cpu: &CPU, options: &Options) {
+    if let Some(path) = &options.dump_tiles {
+        let both_banks = options.tiles_both_banks.unwrap_or(cpu.memory.hardware().is_cgb());
+        save(&cpu.memory.gpu.tile_set_image(options.tiles_bgp, both_banks, &options.palette), path);
+    }
+    if let Some(dir) = &options.dump_maps {
+        if let Err(error) = viewer::save_tile_maps(&cpu.memory.gpu, dir, &options.palette) {
+            eprintln!("Failed to save the tile maps to {}: {}", dir.display(), error);
+        }
+    }
+    if let Some(dir) = &options.dump_oam {
+        if let Err(error) = viewer::save_oam(&cpu.memory.gpu, cpu.memory.oam(), dir, &options.palette) {
+            eprintln!("Failed to save the OAM to {}: {}", dir.display(), error);
+        }
+    }
+}
 
-        cpu.memory.step(cycles);
+fn save(image: &Image, path: &Path) {
+    if let Err(error) = image.save(path, ImageFormat::from_path(path)) {
+        eprintln!("Failed to save {}: {}", path.display(), error);
     }
 }
@@ -1,15 +1,21 @@
 use std::fs::File;
 use std::io::Read;
+use crate::compat::CompatPalettes;
+use crate::dma::{Hdma, OamDma, HDMA_BLOCK_LENGTH};
 use crate::gpu::GPU;
+use crate::hardware::{self, Hardware};
+use crate::joypad::Joypad;
 use crate::timer::Timer;
 use crate::cpu::Cycles;
 use crate::utils::{join_8_to_16, split_16_to_8};
 
 pub struct Memory {
+    hardware: Hardware,
     cart: Vec<u8>,
-    gpu: GPU,
+    pub gpu: GPU,
     switchable_ram: Vec<u8>,
-    ram: Vec<u8>, // in cgb mode this is split in bank 0 and switchable bank 1
+    ram: Vec<u8>, // 8 banks of 4 KiB, bank 0 at C000 and the bank selected by SVBK at D000, 1 on DMG
+    ram_bank: usize, // FF70 as written, CGB only, 0 selects bank 1
     oam: Vec<u8>, // sprites stuff
     io_port: Vec<u8>,
     stack: Vec<u8>, // stack in GMB Z80 is a part of the regular memory, simply called zero-page ram
@@ -17,27 +23,59 @@ pub struct Memory {
     pub interrupt_enable: u8,
     pub interrupt_flag: u8,
     timer: Timer,
+    oam_dma: OamDma,
+    hdma: Hdma, // CGB only
+    dma_stall: Cycles, // CPU cycles owed to VRAM DMA copies
+    timer_stop: Cycles, // CPU cycles STOP still holds the divider and the timer for
+    pub joypad: Joypad,
+    key1: u8, // FF4D, the current speed in bit 7 and the switch request in bit 0
 }
 
+const OAM_OFFSET: usize = 0xfe00;
 const STACK_OFFSET: usize = 0xff80;
+const RAM_BANK_SIZE: usize = 0x1000;
 
 impl Memory {
-    pub fn new(filepath: &str) -> Memory {
+    /// Loads the cartridge, running it as `hardware` or in the mode its header asks for.
+    pub fn new(filepath: &str, hardware: Option<Hardware>) -> Memory {
+        let cart = Memory::read_cartridge(filepath);
+        let hardware = hardware.unwrap_or_else(|| Hardware::detect(&cart));
+        let mut gpu = GPU::new(hardware);
+        if hardware == Hardware::DmgCompat {
+            gpu.set_compat_palettes(&CompatPalettes::from_title(&cart));
+        }
         Memory {
-            cart: Memory::read_cartridge(filepath),
-            gpu: GPU::new(),
+            hardware,
+            cart,
+            gpu,
             switchable_ram: vec![0; 0x2000],
-            ram: vec![0; 0x2000],
+            ram: vec![0; RAM_BANK_SIZE * 8],
+            ram_bank: 0,
             oam: vec![0; 0x100],
             io_port: vec![0; 0x100],
             stack: vec![0; 0x80],
 
             interrupt_enable: 0,
             interrupt_flag: 0,
-            timer: Timer::new(),
+            timer: Timer::new(hardware),
+            oam_dma: OamDma::new(hardware),
+            hdma: Hdma::new(),
+            dma_stall: 0,
+            timer_stop: 0,
+            joypad: Joypad::new(),
+            key1: 0,
         }
     }
 
+    pub fn hardware(&self) -> Hardware {
+        self.hardware
+    }
+
+    /// The title checksum the boot ROM leaves in B, see `hardware::boot_checksum`.
+    pub fn boot_checksum(&self) -> u8 {
+        hardware::boot_checksum(&self.cart)
+    }
+
     fn read_cartridge(filepath: &str) -> Vec<u8> {
         let mut f = File::open(filepath)
             .expect("Failed to read file");
@@ -47,15 +85,31 @@ impl Memory {
         cart
     }
 
+    /// Read as seen by the CPU. While OAM DMA runs, only the 0xff00 page (I/O and HRAM) is reachable.
     pub fn read_8(&self, i: usize) -> u8 {
+        if self.oam_dma.is_active() && i < 0xff00 {
+            return 0xff
+        }
+        self.read_bus(i)
+    }
+
+    fn read_bus(&self, i: usize) -> u8 {
         match i { // TODO implement the "read 0s" and so from invalid regions
             0..=0x7fff => self.cart[i],
-            0x8000..=0x9fff => self.gpu.read_vram(i - 0x8000),
+            0x8000..=0x9fff => if self.gpu.vram_accessible() { self.gpu.read_vram(i - 0x8000) } else { 0xff },
             0xa000..=0xbfff => self.switchable_ram[i - 0xa000],
-            0xc000..=0xdfff => self.ram[i - 0xc000],
-            0xe000..=0xfdff => self.ram[i - 0xe000], // ram echo
+            0xc000..=0xdfff => self.ram[self.ram_index(i)],
+            0xe000..=0xfdff => self.ram[self.ram_index(i - 0x2000)], // ram echo
+            0xfe00..=0xfe9f => if self.gpu.oam_accessible() { self.oam[i - OAM_OFFSET] } else { 0xff },
+            0xfea0..=0xfeff => if self.gpu.oam_accessible() { 0x00 } else { 0xff }, // unusable, locked with OAM
+            0xff00 => self.joypad.read(),
             0xff04..=0xff07 => self.timer.read(i),
             0xff0f => self.interrupt_flag,
+            0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6b => self.gpu.read_register(i),
+            0xff46 => self.oam_dma.read(),
+            0xff4d => if self.hardware.is_cgb() { 0x7e | self.key1 } else { 0xff },
+            0xff51..=0xff55 => if self.hardware.is_cgb() { self.hdma.read(i) } else { 0xff },
+            0xff70 => if self.hardware.is_cgb() { 0xf8 | self.ram_bank as u8 } else { 0xff },
             0xff80..=0xfffe => self.stack[i - STACK_OFFSET],
             0xffff => self.interrupt_enable,
             _ => panic!("mem read {}", i),
@@ -63,20 +117,89 @@ impl Memory {
     }
 
     pub fn write_8(&mut self, i: usize, n: u8) {
+        if self.oam_dma.is_active() && i < 0xff00 {
+            return
+        }
+
         match i { // TODO implement the "do nothing" and so from invalid regions
             0..=0x7fff => self.cart[i] = n,
-            0x8000..=0x9fff => self.gpu.write_vram(i - 0x8000, n),
+            0x8000..=0x9fff => if self.gpu.vram_accessible() { self.gpu.write_vram(i - 0x8000, n) },
             0xa000..=0xbfff => self.switchable_ram[i - 0xa000] = n,
-            0xc000..=0xdfff => self.ram[i - 0xc000] = n,
-            0xe000..=0xfdff => self.ram[i - 0xe000] = n, // ram echo
+            0xc000..=0xdfff => { let index = self.ram_index(i); self.ram[index] = n },
+            0xe000..=0xfdff => { let index = self.ram_index(i - 0x2000); self.ram[index] = n }, // ram echo
+            0xfe00..=0xfe9f => if self.gpu.oam_accessible() { self.oam[i - OAM_OFFSET] = n },
+            0xfea0..=0xfeff => {} // unusable, writes are ignored
+            0xff00 => self.joypad.write(n),
             0xff04..=0xff07 => self.timer.write(i, n),
             0xff0f => self.interrupt_flag = n,
+            0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6b => self.gpu.write_register(i, n),
+            0xff46 => self.oam_dma.write(n),
+            0xff4d => if self.hardware.is_cgb() { self.key1 = (self.key1 & 0x80) | (n & 0x01) },
+            0xff51..=0xff55 => if self.hardware.is_cgb() {
+                self.hdma.write(i, n);
+                while self.hdma_copy_block(false) {}
+            },
+            0xff70 => if self.hardware.is_cgb() { self.ram_bank = (n & 0x07) as usize },
             0xff80..=0xfffe => self.stack[i - STACK_OFFSET] = n,
             0xffff => self.interrupt_enable = n,
             _ => panic!("mem write {}", i),
         }
     }
 
+    /// Copies the next VRAM DMA block if one is due, `hblank` when an HBlank just started. Returns
+    /// whether a block was copied.
+    fn hdma_copy_block(&mut self, hblank: bool) -> bool {
+        let (source, destination) = match self.hdma.next_block(hblank) {
+            Some(block) => block,
+            None => return false,
+        };
+        for offset in 0..HDMA_BLOCK_LENGTH {
+            let value = self.read_bus(source + offset);
+            self.gpu.write_vram(destination + offset, value);
+        }
+        self.dma_stall += Hdma::block_cycles(self.double_speed());
+        true
+    }
+
+    /// CPU cycles the CPU has to stay stalled for the VRAM DMA blocks copied so far.
+    pub fn take_dma_stall(&mut self) -> Cycles {
+        let cycles = self.dma_stall;
+        self.dma_stall = 0;
+        cycles
+    }
+
+    /// Holds the divider and the timer for the next `cycles` CPU cycles, STOP freezes them while the
+    /// CPU is stopped and during the speed switch.
+    pub fn stop_timer(&mut self, cycles: Cycles) {
+        self.timer_stop += cycles;
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.key1 & 0x80 != 0
+    }
+
+    /// Toggles the CGB double speed mode if KEY1 requested it, returns whether it did.
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.hardware.is_cgb() || self.key1 & 0x01 == 0 {
+            return false
+        }
+        self.key1 = (self.key1 ^ 0x80) & 0x80;
+        true
+    }
+
+    /// Index into `ram` of a C000-DFFF address, through the bank selected by SVBK above D000.
+    fn ram_index(&self, i: usize) -> usize {
+        match i {
+            0xc000..=0xcfff => i - 0xc000,
+            _ => self.ram_bank.max(1) * RAM_BANK_SIZE + i - 0xd000,
+        }
+    }
+
+    /// The 40 OAM entries, 4 bytes each.
+    pub fn oam(&self) -> &[u8] {
+        &self.oam[..0xa0]
+    }
+
     pub fn read_16(&self, i: usize) -> u16 {
         join_8_to_16(self.read_8(i), self.read_8(i + 1))
     }
@@ -87,9 +210,27 @@ impl Memory {
         self.write_8(i + 1, ns.1);
     }
 
-    pub fn step(&self, cycles: Cycles) {
-        self.timer.step(cycles);
-        self.timer.update_interrupt_flag(&self.interrupt_flag);
-        self.gpu.step(cycles);
+    /// Runs the other components for `cycles` CPU cycles and returns how many cycles of the normal clock
+    /// they took. In double speed the timer and OAM DMA follow the CPU but the PPU keeps its pace.
+    /// The PPU also keeps running while STOP holds the timer, so the frontends still get frames and
+    /// read the joypad that wakes the CPU up.
+    pub fn step(&mut self, cycles: Cycles) -> Cycles {
+        let dots = if self.double_speed() { cycles / 2 } else { cycles };
+
+        for index in self.oam_dma.step(cycles) {
+            self.oam[index] = self.read_bus(self.oam_dma.source_address(index));
+        }
+
+        let stopped = cycles.min(self.timer_stop);
+        self.timer_stop -= stopped;
+        self.timer.step(cycles - stopped);
+        self.timer.update_interrupt_flag(&mut self.interrupt_flag);
+        self.gpu.step(dots, &self.oam);
+        for _ in 0..self.gpu.take_hblanks() {
+            self.hdma_copy_block(true);
+        }
+        self.gpu.update_interrupt_flag(&mut self.interrupt_flag);
+        self.joypad.update_interrupt_flag(&mut self.interrupt_flag);
+        dots
     }
 }
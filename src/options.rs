@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+
+use crate::color::ColorCorrection;
+use crate::compat::CompatPalettes;
+use crate::filter::{Filter, LcdEffect, Scaler};
+use crate::gpu::Renderer;
+use crate::hardware::Hardware;
+use crate::image::ImageFormat;
+use crate::palette::Palette;
+use crate::terminal::TerminalColors;
+
+pub const USAGE: &str = "Usage: gbemu [options] [rom]
+
+Options:
+    --hardware MODE             dmg, cgb or dmg-compat (default: from the cartridge header)
+    --compat-palette BUTTONS    colors for dmg-compat picked like with the boot ROM button combinations:
+                                up, down, left or right, alone or with +a or +b (default: from the title)
+    --fifo                      emulate mode 3 with the pixel FIFOs
+    --terminal                  play in the terminal, drawn with half blocks
+    --colors 24bit|256          terminal color escapes (default: 24bit)
+    --blend STRENGTH            blend each displayed and saved frame with the previous one, 0 to 0.9
+    --ghosting STRENGTH         let pixels fade out slowly like the DMG LCD, 0 to 0.9
+    --palette NAME|FILE         output colors: grayscale, dmg, pocket, light, high-contrast
+                                or a file with four hex colors (default: grayscale)
+    --color-correction CURVE    CGB colors: none, gbc-lcd or gamma (default: none)
+    --screenshot-every N        save every Nth frame
+    --screenshot-dir DIR        directory for saved frames (default: current)
+    --screenshot-format FORMAT  png or ppm (default: png)
+    --scale SCALER              upscaling of saved frames: 1x to 8x, scale2x, scale3x or xbr2x (default: 1x)
+    --lcd-effect EFFECT         none, grid or scanlines over upscaled frames (default: none)
+    --frames N                  stop after N frames
+    --dump-tiles FILE           save the VRAM tile set when stopping, png or ppm by extension
+    --tiles-banks 1|2           dump VRAM bank 0 only or both banks side by side (default: 2 on CGB)
+    --tiles-bgp                 draw the dumped tiles through BGP instead of fixed greyscale
+    --dump-maps DIR             save both background maps as png and json when stopping
+    --dump-oam DIR              save the OAM entries as png and json when stopping";
+
+const DEFAULT_ROM: &str = "roms/Tetris (World) (Rev A).gb";
+
+pub struct Options {
+    pub rom: String,
+    pub hardware: Option<Hardware>,
+    pub compat_palettes: Option<CompatPalettes>,
+    pub renderer: Renderer,
+    pub palette: Palette,
+    pub color_correction: ColorCorrection,
+    pub terminal: bool,
+    pub terminal_colors: TerminalColors,
+    pub blend: f32,
+    pub ghosting: f32,
+    pub screenshot_every: Option<usize>,
+    pub screenshot_dir: PathBuf,
+    pub screenshot_format: ImageFormat,
+    pub filter: Filter,
+    pub frames: Option<usize>,
+    pub dump_tiles: Option<PathBuf>,
+    pub tiles_both_banks: Option<bool>,
+    pub tiles_bgp: bool,
+    pub dump_maps: Option<PathBuf>,
+    pub dump_oam: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options {
+            rom: DEFAULT_ROM.to_string(),
+            hardware: None,
+            compat_palettes: None,
+            renderer: Renderer::Scanline,
+            palette: Palette::GRAYSCALE,
+            color_correction: ColorCorrection::None,
+            terminal: false,
+            terminal_colors: TerminalColors::TrueColor,
+            blend: 0.0,
+            ghosting: 0.0,
+            screenshot_every: None,
+            screenshot_dir: PathBuf::from("."),
+            screenshot_format: ImageFormat::Png,
+            filter: Filter::NONE,
+            frames: None,
+            dump_tiles: None,
+            tiles_both_banks: None,
+            tiles_bgp: false,
+            dump_maps: None,
+            dump_oam: None,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--hardware" => {
+                    let name = Options::value(&mut args, &arg)?;
+                    options.hardware = Some(Hardware::from_name(&name).ok_or(format!("Unknown hardware {}", name))?);
+                }
+                "--compat-palette" => {
+                    let name = Options::value(&mut args, &arg)?;
+                    options.compat_palettes = Some(CompatPalettes::from_buttons(&name)
+                        .ok_or(format!("Unknown button combination {}", name))?);
+                }
+                "--fifo" => options.renderer = Renderer::Fifo,
+                "--terminal" => options.terminal = true,
+                "--colors" => {
+                    let name = Options::value(&mut args, &arg)?;
+                    options.terminal_colors = TerminalColors::from_name(&name)
+                        .ok_or(format!("Unknown terminal colors {}", name))?;
+                }
+                "--blend" => options.blend = Options::strength(&mut args, &arg)?,
+                "--ghosting" => options.ghosting = Options::strength(&mut args, &arg)?,
+                "--palette" => {
+                    let name = Options::value(&mut args, &arg)?;
+                    options.palette = match Palette::preset(&name) {
+                        Some(palette) => palette,
+                        None => Palette::load(&name)?,
+                    };
+                }
+                "--color-correction" => {
+                    let name = Options::value(&mut args, &arg)?;
+                    options.color_correction = ColorCorrection::from_name(&name)
+                        .ok_or(format!("Unknown color correction {}", name))?;
+                }
+                "--screenshot-every" => options.screenshot_every = Some(Options::count(&mut args, &arg)?),
+                "--screenshot-dir" => options.screenshot_dir = PathBuf::from(Options::value(&mut args, &arg)?),
+                "--screenshot-format" => {
+                    let name = Options::value(&mut args, &arg)?;
+                    options.screenshot_format = ImageFormat::from_name(&name)
+                        .ok_or(format!("Unknown image format {}", name))?;
+                }
+                "--scale" => {
+                    let name = Options::value(&mut args, &arg)?;
+                    options.filter.scaler = Scaler::from_name(&name).ok_or(format!("Unknown scaler {}", name))?;
+                }
+                "--lcd-effect" => {
+                    let name = Options::value(&mut args, &arg)?;
+                    options.filter.effect = LcdEffect::from_name(&name).ok_or(format!("Unknown LCD effect {}", name))?;
+                }
+                "--frames" => options.frames = Some(Options::count(&mut args, &arg)?),
+                "--dump-tiles" => options.dump_tiles = Some(PathBuf::from(Options::value(&mut args, &arg)?)),
+                "--tiles-banks" => {
+                    let banks = Options::value(&mut args, &arg)?;
+                    options.tiles_both_banks = match banks.as_str() {
+                        "1" => Some(false),
+                        "2" => Some(true),
+                        _ => return Err(format!("Invalid bank count {} for {}", banks, arg)),
+                    };
+                }
+                "--tiles-bgp" => options.tiles_bgp = true,
+                "--dump-maps" => options.dump_maps = Some(PathBuf::from(Options::value(&mut args, &arg)?)),
+                "--dump-oam" => options.dump_oam = Some(PathBuf::from(Options::value(&mut args, &arg)?)),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => options.rom = arg,
+            }
+        }
+
+        let dumps = options.dump_tiles.is_some() || options.dump_maps.is_some() || options.dump_oam.is_some();
+        if options.frames.is_none() && dumps {
+            return Err("Dumps are saved when stopping, they need --frames".to_string())
+        }
+        if options.compat_palettes.is_some() && options.hardware != Some(Hardware::DmgCompat) {
+            return Err("--compat-palette needs --hardware dmg-compat".to_string())
+        }
+        Ok(options)
+    }
+
+    /// A positive number of frames.
+    fn count<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<usize, String> {
+        let n = Options::value(args, option)?;
+        match n.parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => Err(format!("Invalid frame count {} for {}", n, option)),
+        }
+    }
+
+    /// A filter strength from 0 to 0.9.
+    fn strength<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<f32, String> {
+        let n = Options::value(args, option)?;
+        match n.parse() {
+            Ok(n) if (0.0..=0.9).contains(&n) => Ok(n),
+            _ => Err(format!("Invalid strength {} for {}", n, option)),
+        }
+    }
+
+    fn value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, String> {
+        args.next().ok_or(format!("Missing value for {}", option))
+    }
+}
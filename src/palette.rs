@@ -0,0 +1,72 @@
+use std::fs;
+
+use crate::image::Rgb;
+
+/// Output colors for the four DMG shades, from the lightest to the darkest. Only used when a frame
+/// is converted for output, emulation always works on the shades.
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+impl Palette {
+    pub const GRAYSCALE: Palette = Palette {
+        colors: [[0xff, 0xff, 0xff], [0xaa, 0xaa, 0xaa], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]],
+    };
+    pub const DMG: Palette = Palette {
+        colors: [[0x9b, 0xbc, 0x0f], [0x8b, 0xac, 0x0f], [0x30, 0x62, 0x30], [0x0f, 0x38, 0x0f]],
+    };
+    pub const POCKET: Palette = Palette {
+        colors: [[0xc4, 0xcf, 0xa1], [0x8b, 0x95, 0x6d], [0x4d, 0x53, 0x3c], [0x1f, 0x1f, 0x1f]],
+    };
+    pub const LIGHT: Palette = Palette {
+        colors: [[0x00, 0xb5, 0x81], [0x00, 0x9a, 0x71], [0x00, 0x69, 0x4a], [0x00, 0x4f, 0x3b]],
+    };
+    /// Shades apart in both brightness and hue, and without relying on red against green.
+    pub const HIGH_CONTRAST: Palette = Palette {
+        colors: [[0xff, 0xff, 0xff], [0xf0, 0xb0, 0x00], [0x00, 0x50, 0xc0], [0x00, 0x00, 0x00]],
+    };
+
+    pub const PRESETS: [(&'static str, Palette); 5] = [
+        ("grayscale", Palette::GRAYSCALE),
+        ("dmg", Palette::DMG),
+        ("pocket", Palette::POCKET),
+        ("light", Palette::LIGHT),
+        ("high-contrast", Palette::HIGH_CONTRAST),
+    ];
+
+    pub fn preset(name: &str) -> Option<Palette> {
+        Palette::PRESETS.iter()
+            .find(|(preset, _)| *preset == name.to_lowercase())
+            .map(|(_, palette)| *palette)
+    }
+
+    /// Reads four colors from a text file, lightest first, written as hex RGB like `#9bbc0f` or `0x9bbc0f`.
+    /// Colors may be separated by whitespace or commas, and everything after `;` on a line is ignored.
+    pub fn load(path: &str) -> Result<Palette, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("Failed to read {}: {}", path, error))?;
+        Palette::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Palette, String> {
+        let colors = text.lines()
+            .map(|line| line.split(';').next().unwrap_or(""))
+            .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|token| !token.is_empty())
+            .map(Palette::parse_color)
+            .collect::<Result<Vec<Rgb>, String>>()?;
+
+        match colors.as_slice() {
+            [c0, c1, c2, c3] => Ok(Palette { colors: [*c0, *c1, *c2, *c3] }),
+            _ => Err(format!("Expected 4 colors, found {}", colors.len())),
+        }
+    }
+
+    fn parse_color(token: &str) -> Result<Rgb, String> {
+        let hex = token.trim_start_matches('#').trim_start_matches("0x");
+        match u32::from_str_radix(hex, 16) {
+            Ok(n) if hex.len() == 6 => Ok([(n >> 16) as u8, (n >> 8) as u8, n as u8]),
+            _ => Err(format!("Invalid color {}", token)),
+        }
+    }
+}
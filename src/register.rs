@@ -1,3 +1,4 @@
+use crate::hardware::Hardware;
 use crate::utils::{join_8_to_16, split_16_to_8};
 
 #[derive(Debug)]
@@ -22,8 +23,17 @@ pub enum Flags {
 }
 
 impl Register {
-    pub fn new() -> Register {
-        Register{ a: 0x01, f: 0xB0, b: 0, c: 0x13, d: 0, e: 0xD8, h: 0x01, l: 0x4D, sp: 0xfffe, pc: 0x100 }
+    /// Values left by the boot ROM. In DMG-compat mode B holds `checksum` (see `boot_checksum`) and HL
+    /// depends on it.
+    pub fn new(hardware: Hardware, checksum: u8) -> Register {
+        match hardware {
+            Hardware::Dmg => Register{ a: 0x01, f: 0xB0, b: 0, c: 0x13, d: 0, e: 0xD8, h: 0x01, l: 0x4D, sp: 0xfffe, pc: 0x100 },
+            Hardware::Cgb => Register{ a: 0x11, f: 0x80, b: 0, c: 0, d: 0xFF, e: 0x56, h: 0, l: 0x0D, sp: 0xfffe, pc: 0x100 },
+            Hardware::DmgCompat => {
+                let (h, l) = if checksum == 0x43 || checksum == 0x58 { (0x99, 0x1A) } else { (0, 0x7C) };
+                Register{ a: 0x11, f: 0x80, b: checksum, c: 0, d: 0, e: 0x08, h, l, sp: 0xfffe, pc: 0x100 }
+            }
+        }
     }
 
     pub fn get_hl(&self) -> u16 {
@@ -0,0 +1,186 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::image::{Image, Rgb};
+use crate::joypad::{Button, Joypad, BUTTONS};
+
+/// Terminals only report key presses, so a key counts as held for this many frames after its
+/// last press or auto-repeat.
+const HOLD_FRAMES: usize = 8;
+
+#[derive(Copy,Clone,PartialEq,Debug)]
+pub enum TerminalColors {
+    TrueColor,
+    Ansi256,
+}
+
+impl TerminalColors {
+    pub fn from_name(name: &str) -> Option<TerminalColors> {
+        match name {
+            "24bit" | "truecolor" => Some(TerminalColors::TrueColor),
+            "256" => Some(TerminalColors::Ansi256),
+            _ => None,
+        }
+    }
+}
+
+/// Frontend drawing frames with the upper half block, two pixels per character cell, and reading
+/// the keys from stdin in raw mode.
+///
+/// Keys: arrows or WASD for the pad, X for A, Z for B, Enter for Start, Backspace or Space for Select,
+/// Q or Ctrl-C to quit.
+pub struct Terminal {
+    colors: TerminalColors,
+    saved_mode: String,
+    input: Receiver<u8>,
+    held: [usize; 8], // frames left for each button of `BUTTONS`
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl Terminal {
+    pub fn new(colors: TerminalColors, frame_rate: f64) -> io::Result<Terminal> {
+        let saved_mode = Terminal::stty(&["-g"])?;
+        Terminal::stty(&["raw", "-echo"])?;
+
+        let (sender, input) = mpsc::channel();
+        thread::spawn(move || {
+            for byte in io::stdin().lock().bytes() {
+                match byte {
+                    Ok(byte) => if sender.send(byte).is_err() { break },
+                    Err(_) => break,
+                }
+            }
+        });
+
+        print!("\x1b[?25l\x1b[2J"); // hide the cursor, clear the screen
+        io::stdout().flush()?;
+
+        Ok(Terminal {
+            colors,
+            saved_mode: saved_mode.trim().to_string(),
+            input,
+            held: [0; 8],
+            frame_duration: Duration::from_secs_f64(1.0 / frame_rate),
+            next_frame: Instant::now(),
+        })
+    }
+
+    fn stty(args: &[&str]) -> io::Result<String> {
+        let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    pub fn draw(&mut self, image: &Image) -> io::Result<()> {
+        let mut out = String::with_capacity(image.width * image.height * 20);
+        out.push_str("\x1b[H");
+
+        for y in (0..image.height).step_by(2) {
+            let mut last = None;
+            for x in 0..image.width {
+                let top = image.pixels[y * image.width + x];
+                let bottom = if y + 1 < image.height { image.pixels[(y + 1) * image.width + x] } else { [0, 0, 0] };
+                if last != Some((top, bottom)) {
+                    out.push_str(&self.color_escape(38, top));
+                    out.push_str(&self.color_escape(48, bottom));
+                    last = Some((top, bottom));
+                }
+                out.push('\u{2580}');
+            }
+            out.push_str("\x1b[0m\r\n");
+        }
+
+        let mut stdout = io::stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// SGR escape for a foreground (38) or background (48) color.
+    fn color_escape(&self, layer: u8, rgb: Rgb) -> String {
+        match self.colors {
+            TerminalColors::TrueColor => format!("\x1b[{};2;{};{};{}m", layer, rgb[0], rgb[1], rgb[2]),
+            TerminalColors::Ansi256 => format!("\x1b[{};5;{}m", layer, Terminal::ansi_256(rgb)),
+        }
+    }
+
+    /// Nearest entry of the xterm 256 color palette, from the 6x6x6 cube or the grey ramp.
+    fn ansi_256(rgb: Rgb) -> u8 {
+        let [r, g, b] = rgb;
+        if r == g && g == b {
+            return match r {
+                0..=7 => 16,
+                249..=255 => 231,
+                _ => 232 + ((r - 8) / 10).min(23), // the ramp has 24 steps, 232 to 255
+            }
+        }
+        let level = |c: u8| ((c as u16 * 5 + 127) / 255) as u8;
+        16 + 36 * level(r) + 6 * level(g) + level(b)
+    }
+
+    /// Applies the keys read since the last frame to `joypad`. Returns false when the user asked to quit.
+    pub fn update_joypad(&mut self, joypad: &mut Joypad) -> bool {
+        let bytes: Vec<u8> = self.input.try_iter().collect();
+
+        let mut i = 0;
+        while i < bytes.len() {
+            let button = match bytes[i] {
+                b'q' | b'Q' | 0x03 => return false,
+                0x1b if bytes.get(i + 1) == Some(&b'[') && i + 2 < bytes.len() => {
+                    i += 2;
+                    match bytes[i] {
+                        b'A' => Some(Button::Up),
+                        b'B' => Some(Button::Down),
+                        b'C' => Some(Button::Right),
+                        b'D' => Some(Button::Left),
+                        _ => None,
+                    }
+                }
+                b'w' | b'W' => Some(Button::Up),
+                b's' | b'S' => Some(Button::Down),
+                b'd' | b'D' => Some(Button::Right),
+                b'a' | b'A' => Some(Button::Left),
+                b'x' | b'X' => Some(Button::A),
+                b'z' | b'Z' => Some(Button::B),
+                b'\r' | b'\n' => Some(Button::Start),
+                b' ' | 0x7f | 0x08 => Some(Button::Select),
+                _ => None,
+            };
+            if let Some(button) = button {
+                let index = BUTTONS.iter().position(|b| *b == button).unwrap();
+                self.held[index] = HOLD_FRAMES;
+            }
+            i += 1;
+        }
+
+        for (held, button) in self.held.iter_mut().zip(BUTTONS.iter()) {
+            joypad.set(*button, *held > 0);
+            *held = held.saturating_sub(1);
+        }
+        true
+    }
+
+    /// Sleeps until the next frame is due, so the game runs at its real speed.
+    pub fn wait_frame(&mut self) {
+        self.next_frame += self.frame_duration;
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else {
+            self.next_frame = now;
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\r\n");
+        let _ = io::stdout().flush();
+        let _ = Terminal::stty(&[&self.saved_mode]);
+    }
+}
@@ -1,19 +1,37 @@
 use crate::cpu::Cycles;
+use crate::hardware::Hardware;
+use crate::interrupt::Interrupts;
+
+const CLOCK_FREQUENCY: usize = 4194304; // Hertz
+const DIVIDER_CYCLES: Cycles = 256;
+const DMG_BOOT_DIVIDER: u16 = 0xabcc; // internal 16 bit divider when the boot ROM hands over, DIV is its high byte
+const CGB_BOOT_DIVIDER: u16 = 0x1ea0;
 
 pub struct Timer {
     divider: u8,
     counter: u8, // TIMA
     modulo: u8, // TMA
     control: u8, // TAC
+
+    divider_cycles: Cycles,
+    counter_cycles: Cycles,
+    overflow: bool,
 }
 
 impl Timer {
-    pub fn new() -> Timer {
+    /// Starts where the boot ROM of `hardware` leaves the divider. The DMG-compat boot path varies with
+    /// the title, it starts from the CGB value.
+    pub fn new(hardware: Hardware) -> Timer {
+        let divider = if hardware == Hardware::Dmg { DMG_BOOT_DIVIDER } else { CGB_BOOT_DIVIDER };
         Timer {
-            divider: 0,
+            divider: (divider >> 8) as u8,
             counter: 0,
             modulo: 0,
             control: 0,
+
+            divider_cycles: (divider & 0xff) as Cycles,
+            counter_cycles: 0,
+            overflow: false,
         }
     }
 
@@ -29,7 +47,7 @@ impl Timer {
 
     pub fn write(&mut self, i: usize, n: u8) {
         match i {
-            0xff04 => self.divider = 0,
+            0xff04 => { self.divider = 0; self.divider_cycles = 0 },
             0xff05 => self.counter = n,
             0xff06 => self.modulo = n,
             0xff07 => self.control = n, // todo to have an enabled bool flag?
@@ -47,14 +65,36 @@ impl Timer {
         }
     }
 
-    pub fn step(&self, cycles: Cycles) {
-        let overflow = false;
-        if overflow {
+    pub fn step(&mut self, cycles: Cycles) {
+        self.divider_cycles += cycles;
+        while self.divider_cycles >= DIVIDER_CYCLES {
+            self.divider_cycles -= DIVIDER_CYCLES;
+            self.divider = self.divider.wrapping_add(1);
+        }
+
+        if self.control & 0b100 == 0 {
+            return
+        }
+
+        let counter_period = CLOCK_FREQUENCY / Timer::frequency((self.control & 0b11) as usize);
+        self.counter_cycles += cycles;
+        while self.counter_cycles >= counter_period {
+            self.counter_cycles -= counter_period;
 
+            let (counter, overflow) = self.counter.overflowing_add(1);
+            if overflow {
+                self.counter = self.modulo;
+                self.overflow = true;
+            } else {
+                self.counter = counter;
+            }
         }
     }
 
-    pub fn update_interrupt_flag(&self, flags: &u8) {
-        panic!("Unimplemented")
+    pub fn update_interrupt_flag(&mut self, flags: &mut u8) {
+        if self.overflow {
+            *flags |= Interrupts::Timer as u8;
+            self.overflow = false;
+        }
     }
 }
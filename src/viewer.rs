@@ -0,0 +1,75 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::gpu::{GPU, Sprite, SpriteFlags};
+use crate::image::{ImageFormat, Rgb};
+use crate::palette::Palette;
+
+pub const MAP_ADDRESSES: [usize; 2] = [0x9800, 0x9c00];
+const VIEWPORT: Rgb = [0xff, 0x00, 0x00];
+const OAM_BACKGROUND: Rgb = [0xff, 0x00, 0xff];
+
+/// The tile numbers of a map, 32 rows of 32, with the scroll registers. On CGB the bank 1 attributes of
+/// each entry follow in the same layout.
+pub fn tile_map_json(gpu: &GPU, map_address: usize) -> String {
+    let mut fields = vec![
+        format!("\"address\": \"{:#06x}\"", map_address),
+        format!("\"scx\": {}", gpu.read_register(0xff43)),
+        format!("\"scy\": {}", gpu.read_register(0xff42)),
+        format!("\"tiles\": {}", map_rows_json(gpu.tile_map(map_address))),
+    ];
+    if let Some(attributes) = gpu.tile_map_attributes(map_address) {
+        fields.push(format!("\"attributes\": {}", map_rows_json(attributes)));
+    }
+    format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+}
+
+fn map_rows_json(map: &[u8]) -> String {
+    let rows: Vec<String> = map
+        .chunks(32)
+        .map(|row| {
+            let entries: Vec<String> = row.iter().map(|entry| entry.to_string()).collect();
+            format!("    [{}]", entries.join(", "))
+        })
+        .collect();
+    format!("[\n{}\n  ]", rows.join(",\n"))
+}
+
+/// The 40 OAM entries with their raw bytes, screen position and decoded flags, with the DMG palette
+/// bit and the CGB palette and bank.
+pub fn oam_json(oam: &[u8]) -> String {
+    let entries: Vec<String> = (0..40)
+        .map(|index| {
+            let sprite = Sprite::from_oam(oam, index);
+            format!(
+                "  {{\"index\": {}, \"y\": {}, \"x\": {}, \"screen_y\": {}, \"screen_x\": {}, \"tile\": {}, \"flags\": {}, \
+                 \"palette\": {}, \"cgb_palette\": {}, \"bank\": {}, \"x_flip\": {}, \"y_flip\": {}, \
+                 \"behind_background\": {}}}",
+                index, sprite.y, sprite.x, sprite.y as i16 - 16, sprite.x as i16 - 8, sprite.tile, sprite.flags,
+                sprite.flag(SpriteFlags::Palette) as u8, sprite.cgb_palette(), sprite.flag(SpriteFlags::Bank) as u8,
+                sprite.flag(SpriteFlags::XFlip), sprite.flag(SpriteFlags::YFlip),
+                sprite.flag(SpriteFlags::BehindBackground)
+            )
+        })
+        .collect();
+
+    format!("[\n{}\n]\n", entries.join(",\n"))
+}
+
+/// Saves `map_9800` and `map_9c00` as PNG and JSON into `dir`.
+pub fn save_tile_maps(gpu: &GPU, dir: &Path, palette: &Palette) -> io::Result<()> {
+    for map_address in MAP_ADDRESSES.iter() {
+        let name = format!("map_{:04x}", map_address);
+        gpu.tile_map_image(*map_address, palette, VIEWPORT)
+            .save(&dir.join(format!("{}.png", name)), ImageFormat::Png)?;
+        fs::write(dir.join(format!("{}.json", name)), tile_map_json(gpu, *map_address))?;
+    }
+    Ok(())
+}
+
+/// Saves `oam` as PNG and JSON into `dir`.
+pub fn save_oam(gpu: &GPU, oam: &[u8], dir: &Path, palette: &Palette) -> io::Result<()> {
+    gpu.oam_image(oam, palette, OAM_BACKGROUND).save(&dir.join("oam.png"), ImageFormat::Png)?;
+    fs::write(dir.join("oam.json"), oam_json(oam))
+}